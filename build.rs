@@ -1,10 +1,84 @@
-use cargo_lock::Lockfile;
+use {
+    cargo_lock::Lockfile,
+    std::{env, path::PathBuf},
+};
 
 fn main() -> anyhow::Result<()> {
     // Proto
     let mut config = prost_build::Config::new();
     config.boxed(".blockdaemon.solana.accountsdb_plugin_kafka.types.MessageWrapper");
     config.protoc_arg("--experimental_allow_proto3_optional");
+    config.type_attribute(".", "#[derive(serde::Serialize)]");
+    config.type_attribute(".", "#[serde(rename_all = \"snake_case\")]");
+    for (fields, serializer) in [
+        (
+            &[
+                "UpdateAccountEvent.pubkey",
+                "UpdateAccountEvent.owner",
+                "MessageAddressTableLookup.account_key",
+                "V0Message.recent_block_hash",
+                "LegacyMessage.recent_block_hash",
+                "SanitizedTransaction.message_hash",
+                "TransactionEvent.signature",
+                "EntryEvent.hash",
+                "StartupCompleteEvent.ProgramAccountCount.program",
+                "TokenAccountState.mint",
+                "TokenAccountState.owner",
+                "AccountKey.pubkey",
+                "ReturnData.program_id",
+            ][..],
+            "base58",
+        ),
+        (
+            &[
+                "LoadedAddresses.writable",
+                "LoadedAddresses.readonly",
+                "V0Message.account_keys",
+                "LegacyMessage.account_keys",
+                "SanitizedTransaction.signatures",
+            ][..],
+            "base58_vec",
+        ),
+        (
+            &[
+                "UpdateAccountEvent.txn_signature",
+                "TokenAccountState.delegate",
+                "TokenAccountState.close_authority",
+                "MintState.mint_authority",
+                "MintState.freeze_authority",
+            ][..],
+            "base58_option",
+        ),
+        (
+            &[
+                "UpdateAccountEvent.data",
+                "AccountDataDelta.Range.data",
+                "TokenExtension.data",
+                "ReturnData.data",
+                "CompiledInstruction.data",
+            ][..],
+            "base64",
+        ),
+        (&["SlotStatusEvent.status"][..], "slot_status"),
+        (&["UpdateAccountEvent.data_codec"][..], "data_codec"),
+        (&["TokenAccountState.state"][..], "token_account_status"),
+        (&["AccountKey.source"][..], "account_key_source"),
+        (
+            &["ParsedInstructionInfo.info", "AnchorDecoded.json"][..],
+            "json",
+        ),
+    ] {
+        for field in fields {
+            config.field_attribute(
+                format!(".blockdaemon.solana.accountsdb_plugin_kafka.types.{field}"),
+                format!("#[serde(serialize_with = \"crate::json::{serializer}\")]"),
+            );
+        }
+    }
+    // Descriptors for the message indexes of Schema Registry framing
+    config.file_descriptor_set_path(
+        PathBuf::from(env::var("OUT_DIR")?).join("event_descriptor_set.bin"),
+    );
     config.compile_protos(&["proto/event.proto"], &["proto/"])?;
 
     // Version metrics
@@ -0,0 +1,526 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding of Anchor program accounts and instructions with their IDL.
+
+use {
+    crate::{prom::ANCHOR_UNKNOWN_DISCRIMINATORS_TOTAL, AnchorDecoded},
+    base64::{engine::general_purpose::STANDARD as BASE64, Engine},
+    heck::ToSnakeCase,
+    log::debug,
+    serde::Deserialize,
+    serde_json::{json, Map, Value},
+    solana_program::{hash::hashv, pubkey::Pubkey},
+    std::{collections::HashMap, fs::File, path::PathBuf},
+};
+
+/// Nesting limit of defined types, against types that contain themselves.
+const MAX_DEPTH: usize = 64;
+
+/// Subset of the Anchor IDL needed to decode accounts and instructions.
+#[derive(Debug, Deserialize)]
+struct Idl {
+    #[serde(default)]
+    instructions: Vec<IdlInstruction>,
+    #[serde(default)]
+    accounts: Vec<IdlAccount>,
+    #[serde(default)]
+    types: Vec<IdlTypeDef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdlInstruction {
+    name: String,
+    #[serde(default)]
+    args: Vec<IdlField>,
+    /// Explicit discriminator of newer IDLs.
+    #[serde(default)]
+    discriminator: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdlAccount {
+    name: String,
+    /// Newer IDLs define the type of the account in `types` instead.
+    #[serde(default, rename = "type")]
+    ty: Option<IdlTypeDefTy>,
+    #[serde(default)]
+    discriminator: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdlTypeDef {
+    name: String,
+    #[serde(rename = "type")]
+    ty: IdlTypeDefTy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum IdlTypeDefTy {
+    Struct {
+        #[serde(default)]
+        fields: IdlFields,
+    },
+    Enum {
+        variants: Vec<IdlVariant>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct IdlVariant {
+    name: String,
+    #[serde(default)]
+    fields: IdlFields,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum IdlFields {
+    Named(Vec<IdlField>),
+    Tuple(Vec<IdlType>),
+}
+
+impl Default for IdlFields {
+    fn default() -> Self {
+        Self::Named(Vec::new())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct IdlField {
+    name: String,
+    #[serde(rename = "type")]
+    ty: IdlType,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum IdlType {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    U128,
+    I128,
+    F32,
+    F64,
+    Bytes,
+    String,
+    #[serde(alias = "pubkey")]
+    PublicKey,
+    Vec(Box<IdlType>),
+    Option(Box<IdlType>),
+    #[serde(rename = "coption")]
+    COption(Box<IdlType>),
+    Array(Box<IdlType>, usize),
+    Defined(IdlDefined),
+}
+
+/// A reference to a type in `types`, by name or by `{"name": ...}` in newer IDLs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum IdlDefined {
+    Name(String),
+    Object { name: String },
+}
+
+impl IdlDefined {
+    fn name(&self) -> &str {
+        match self {
+            Self::Name(name) | Self::Object { name } => name,
+        }
+    }
+}
+
+/// Decoding tables of one program.
+#[derive(Debug, Default)]
+struct ProgramIdl {
+    accounts: HashMap<[u8; 8], (String, IdlTypeDefTy)>,
+    instructions: HashMap<[u8; 8], (String, IdlFields)>,
+    types: HashMap<String, IdlTypeDefTy>,
+}
+
+/// Decodes accounts and instructions of Anchor programs by their discriminator.
+#[derive(Debug, Default)]
+pub struct AnchorDecoder {
+    programs: HashMap<Pubkey, ProgramIdl>,
+}
+
+impl AnchorDecoder {
+    /// Load the IDL files of the programs.
+    pub fn new(idls: &HashMap<Pubkey, PathBuf>) -> Result<Self, String> {
+        let mut decoder = Self::default();
+        for (program_id, path) in idls {
+            let file = File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            let idl: Idl =
+                serde_json::from_reader(file).map_err(|e| format!("{}: {e}", path.display()))?;
+            decoder.add(*program_id, idl)?;
+        }
+        Ok(decoder)
+    }
+
+    fn add(&mut self, program_id: Pubkey, idl: Idl) -> Result<(), String> {
+        let mut program = ProgramIdl {
+            types: idl
+                .types
+                .into_iter()
+                .map(|def| (def.name, def.ty))
+                .collect(),
+            ..ProgramIdl::default()
+        };
+        for account in idl.accounts {
+            let ty =
+                match account.ty {
+                    Some(ty) => ty,
+                    None => program.types.get(&account.name).cloned().ok_or_else(|| {
+                        format!("{program_id}: no type for account {}", account.name)
+                    })?,
+                };
+            let discriminator =
+                discriminator(account.discriminator, &format!("account:{}", account.name))?;
+            program.accounts.insert(discriminator, (account.name, ty));
+        }
+        for instruction in idl.instructions {
+            // Anchor names instruction discriminators after the snake case Rust function.
+            let namespace = format!("global:{}", instruction.name.to_snake_case());
+            let discriminator = discriminator(instruction.discriminator, &namespace)?;
+            program.instructions.insert(
+                discriminator,
+                (instruction.name, IdlFields::Named(instruction.args)),
+            );
+        }
+        self.programs.insert(program_id, program);
+        Ok(())
+    }
+
+    /// Decode the data of an account owned by a program with an IDL.
+    pub fn decode_account(&self, owner: &[u8], data: &[u8]) -> Option<AnchorDecoded> {
+        let program = self.programs.get(&Pubkey::try_from(owner).ok()?)?;
+        let Some((name, ty)) = lookup(&program.accounts, data) else {
+            ANCHOR_UNKNOWN_DISCRIMINATORS_TOTAL
+                .with_label_values(&["account"])
+                .inc();
+            return None;
+        };
+        let decoded = Reader::new(program, &data[8..]).type_def(ty, 0);
+        Self::decoded(name, decoded)
+    }
+
+    /// Decode the data of an instruction of a program with an IDL.
+    pub fn decode_instruction(&self, program_id: &Pubkey, data: &[u8]) -> Option<AnchorDecoded> {
+        let program = self.programs.get(program_id)?;
+        let Some((name, args)) = lookup(&program.instructions, data) else {
+            ANCHOR_UNKNOWN_DISCRIMINATORS_TOTAL
+                .with_label_values(&["instruction"])
+                .inc();
+            return None;
+        };
+        let decoded = Reader::new(program, &data[8..]).fields(args, 0);
+        Self::decoded(name, decoded)
+    }
+
+    fn decoded(name: &str, decoded: Option<Value>) -> Option<AnchorDecoded> {
+        match decoded {
+            Some(value) => Some(AnchorDecoded {
+                name: name.to_owned(),
+                json: value.to_string(),
+            }),
+            None => {
+                debug!("Failed to decode {name} with its Anchor IDL");
+                None
+            }
+        }
+    }
+}
+
+fn lookup<'a, T>(table: &'a HashMap<[u8; 8], T>, data: &[u8]) -> Option<&'a T> {
+    let discriminator = <[u8; 8]>::try_from(data.get(..8)?).ok()?;
+    table.get(&discriminator)
+}
+
+/// The explicit discriminator, or the first bytes of the hash of the namespaced name.
+fn discriminator(explicit: Option<Vec<u8>>, namespace: &str) -> Result<[u8; 8], String> {
+    match explicit {
+        Some(bytes) => {
+            <[u8; 8]>::try_from(bytes).map_err(|_| format!("{namespace}: invalid discriminator"))
+        }
+        None => Ok(hashv(&[namespace.as_bytes()]).to_bytes()[..8]
+            .try_into()
+            .unwrap()),
+    }
+}
+
+/// Borsh reader turning data into JSON by IDL types.
+struct Reader<'a> {
+    program: &'a ProgramIdl,
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(program: &'a ProgramIdl, data: &'a [u8]) -> Self {
+        Self { program, data }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.data.len() {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    fn len(&mut self) -> Option<usize> {
+        Some(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn type_def(&mut self, ty: &IdlTypeDefTy, depth: usize) -> Option<Value> {
+        match ty {
+            IdlTypeDefTy::Struct { fields } => self.fields(fields, depth),
+            IdlTypeDefTy::Enum { variants } => {
+                let variant = variants.get(self.array::<1>()?[0] as usize)?;
+                match &variant.fields {
+                    IdlFields::Named(fields) if fields.is_empty() => {
+                        Some(Value::String(variant.name.clone()))
+                    }
+                    fields => {
+                        let mut object = Map::new();
+                        object.insert(variant.name.clone(), self.fields(fields, depth)?);
+                        Some(Value::Object(object))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Named fields as an object, tuple fields as an array.
+    fn fields(&mut self, fields: &IdlFields, depth: usize) -> Option<Value> {
+        match fields {
+            IdlFields::Named(fields) => fields
+                .iter()
+                .map(|field| Some((field.name.clone(), self.value(&field.ty, depth)?)))
+                .collect::<Option<Map<_, _>>>()
+                .map(Value::Object),
+            IdlFields::Tuple(types) => types
+                .iter()
+                .map(|ty| self.value(ty, depth))
+                .collect::<Option<Vec<_>>>()
+                .map(Value::Array),
+        }
+    }
+
+    fn value(&mut self, ty: &IdlType, depth: usize) -> Option<Value> {
+        Some(match ty {
+            IdlType::Bool => Value::Bool(self.array::<1>()?[0] != 0),
+            IdlType::U8 => json!(self.array::<1>()?[0]),
+            IdlType::I8 => json!(i8::from_le_bytes(self.array()?)),
+            IdlType::U16 => json!(u16::from_le_bytes(self.array()?)),
+            IdlType::I16 => json!(i16::from_le_bytes(self.array()?)),
+            IdlType::U32 => json!(u32::from_le_bytes(self.array()?)),
+            IdlType::I32 => json!(i32::from_le_bytes(self.array()?)),
+            IdlType::U64 => json!(u64::from_le_bytes(self.array()?)),
+            IdlType::I64 => json!(i64::from_le_bytes(self.array()?)),
+            // Beyond the precision of JSON numbers in most parsers.
+            IdlType::U128 => json!(u128::from_le_bytes(self.array()?).to_string()),
+            IdlType::I128 => json!(i128::from_le_bytes(self.array()?).to_string()),
+            IdlType::F32 => json!(f32::from_le_bytes(self.array()?)),
+            IdlType::F64 => json!(f64::from_le_bytes(self.array()?)),
+            IdlType::Bytes => {
+                let len = self.len()?;
+                json!(BASE64.encode(self.take(len)?))
+            }
+            IdlType::String => {
+                let len = self.len()?;
+                json!(std::str::from_utf8(self.take(len)?).ok()?)
+            }
+            IdlType::PublicKey => json!(bs58::encode(self.take(32)?).into_string()),
+            IdlType::Vec(ty) => {
+                let len = self.len()?;
+                // The length is not trusted for allocations; items run out with the data.
+                (0..len)
+                    .map(|_| self.value(ty, depth))
+                    .collect::<Option<Vec<_>>>()
+                    .map(Value::Array)?
+            }
+            IdlType::Option(ty) => match self.array::<1>()?[0] {
+                0 => Value::Null,
+                1 => self.value(ty, depth)?,
+                _ => return None,
+            },
+            IdlType::COption(ty) => match u32::from_le_bytes(self.array()?) {
+                0 => Value::Null,
+                1 => self.value(ty, depth)?,
+                _ => return None,
+            },
+            IdlType::Array(ty, len) => (0..*len)
+                .map(|_| self.value(ty, depth))
+                .collect::<Option<Vec<_>>>()
+                .map(Value::Array)?,
+            IdlType::Defined(defined) => {
+                if depth >= MAX_DEPTH {
+                    return None;
+                }
+                let ty = self.program.types.get(defined.name())?;
+                self.type_def(ty, depth + 1)?
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{discriminator, AnchorDecoder, Idl},
+        crate::AnchorDecoded,
+        heck::ToSnakeCase,
+        serde_json::{json, Value},
+        solana_program::pubkey::Pubkey,
+    };
+
+    const IDL: &str = r#"{
+        "version": "0.1.0",
+        "name": "counter",
+        "instructions": [
+            {
+                "name": "setCount",
+                "accounts": [{ "name": "counter", "isMut": true, "isSigner": false }],
+                "args": [
+                    { "name": "count", "type": "u64" },
+                    { "name": "mode", "type": { "defined": "Mode" } }
+                ]
+            }
+        ],
+        "accounts": [
+            {
+                "name": "Counter",
+                "type": {
+                    "kind": "struct",
+                    "fields": [
+                        { "name": "authority", "type": "publicKey" },
+                        { "name": "count", "type": "u64" },
+                        { "name": "label", "type": { "option": "string" } },
+                        { "name": "history", "type": { "vec": "i16" } },
+                        { "name": "mode", "type": { "defined": "Mode" } }
+                    ]
+                }
+            }
+        ],
+        "types": [
+            {
+                "name": "Mode",
+                "type": {
+                    "kind": "enum",
+                    "variants": [
+                        { "name": "Off" },
+                        { "name": "Step", "fields": ["u8"] },
+                        { "name": "Range", "fields": [{ "name": "max", "type": "u32" }] }
+                    ]
+                }
+            }
+        ]
+    }"#;
+
+    fn decoder(program_id: Pubkey) -> AnchorDecoder {
+        let mut decoder = AnchorDecoder::default();
+        let idl: Idl = serde_json::from_str(IDL).unwrap();
+        decoder.add(program_id, idl).unwrap();
+        decoder
+    }
+
+    fn decoded_json(decoded: Option<AnchorDecoded>) -> (String, Value) {
+        let decoded = decoded.unwrap();
+        (decoded.name, serde_json::from_str(&decoded.json).unwrap())
+    }
+
+    #[test]
+    fn test_discriminator() {
+        assert_eq!("setCount".to_snake_case(), "set_count");
+        assert_eq!("initializeV2".to_snake_case(), "initialize_v2");
+        assert_eq!("setURI".to_snake_case(), "set_uri");
+        // The discriminator of Anchor's `initialize` instruction.
+        assert_eq!(
+            discriminator(None, "global:initialize").unwrap(),
+            [175, 175, 109, 31, 13, 152, 155, 237]
+        );
+        assert!(discriminator(Some(vec![1, 2]), "global:initialize").is_err());
+    }
+
+    #[test]
+    fn test_decode_account() {
+        let program_id = Pubkey::new_unique();
+        let decoder = decoder(program_id);
+        let authority = Pubkey::new_unique();
+
+        let mut data = discriminator(None, "account:Counter").unwrap().to_vec();
+        data.extend_from_slice(authority.as_ref());
+        data.extend_from_slice(&7u64.to_le_bytes());
+        data.extend_from_slice(&[1, 2, 0, 0, 0]);
+        data.extend_from_slice(b"hi");
+        data.extend_from_slice(&[2, 0, 0, 0, 0xff, 0xff, 3, 0]);
+        data.extend_from_slice(&[2, 10, 0, 0, 0]);
+        // Trailing space of the account is ignored.
+        data.extend_from_slice(&[0; 16]);
+
+        assert_eq!(
+            decoded_json(decoder.decode_account(program_id.as_ref(), &data)),
+            (
+                "Counter".to_owned(),
+                json!({
+                    "authority": authority.to_string(),
+                    "count": 7,
+                    "label": "hi",
+                    "history": [-1, 3],
+                    "mode": { "Range": { "max": 10 } },
+                })
+            )
+        );
+
+        data[0] ^= 1;
+        assert_eq!(decoder.decode_account(program_id.as_ref(), &data), None);
+        assert_eq!(
+            decoder.decode_account(Pubkey::new_unique().as_ref(), &data),
+            None
+        );
+    }
+
+    #[test]
+    fn test_decode_instruction() {
+        let program_id = Pubkey::new_unique();
+        let decoder = decoder(program_id);
+
+        let mut data = discriminator(None, "global:set_count").unwrap().to_vec();
+        data.extend_from_slice(&42u64.to_le_bytes());
+        data.extend_from_slice(&[1, 5]);
+        assert_eq!(
+            decoded_json(decoder.decode_instruction(&program_id, &data)),
+            (
+                "setCount".to_owned(),
+                json!({ "count": 42, "mode": { "Step": [5] } })
+            )
+        );
+
+        // Data cut short does not decode.
+        assert_eq!(decoder.decode_instruction(&program_id, &data[..12]), None);
+    }
+}
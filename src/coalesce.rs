@@ -0,0 +1,104 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{prom::COALESCED_ACCOUNTS_TOTAL, UpdateAccountEvent},
+    std::{
+        collections::{hash_map::Entry, BTreeMap, HashMap},
+        mem,
+        sync::Mutex,
+    },
+};
+
+/// Latest account updates of a slot by pubkey, with their topics.
+type SlotAccounts = HashMap<Vec<u8>, (String, UpdateAccountEvent)>;
+
+/// Keeps only the update with the highest write version of each account per slot.
+#[derive(Debug, Default)]
+pub struct Coalescer {
+    slots: Mutex<BTreeMap<u64, SlotAccounts>>,
+}
+
+impl Coalescer {
+    /// Hold an account update, replacing older updates of the account in the same slot.
+    pub fn push(&self, topic: &str, event: UpdateAccountEvent) {
+        let mut slots = self.slots.lock().unwrap();
+        let accounts = slots.entry(event.slot).or_default();
+        match accounts.entry(event.pubkey.clone()) {
+            Entry::Occupied(mut entry) => {
+                COALESCED_ACCOUNTS_TOTAL.inc();
+                if event.write_version > entry.get().1.write_version {
+                    entry.insert((topic.to_owned(), event));
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((topic.to_owned(), event));
+            }
+        }
+    }
+
+    /// Take the updates held for the slot and all slots before it, in slot and write version
+    /// order.
+    pub fn flush(&self, slot: u64) -> Vec<(String, UpdateAccountEvent)> {
+        let mut slots = self.slots.lock().unwrap();
+        let later = slots.split_off(&slot.saturating_add(1));
+        mem::replace(&mut *slots, later)
+            .into_values()
+            .flat_map(|accounts| {
+                let mut events = accounts.into_values().collect::<Vec<_>>();
+                events.sort_by_key(|(_, event)| event.write_version);
+                events
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::Coalescer, crate::UpdateAccountEvent};
+
+    fn account(slot: u64, pubkey: u8, write_version: u64) -> UpdateAccountEvent {
+        UpdateAccountEvent {
+            slot,
+            pubkey: vec![pubkey; 32],
+            write_version,
+            ..UpdateAccountEvent::default()
+        }
+    }
+
+    #[test]
+    fn test_coalescer() {
+        let coalescer = Coalescer::default();
+        coalescer.push("accounts", account(10, 1, 5));
+        coalescer.push("accounts", account(10, 2, 3));
+        coalescer.push("accounts", account(10, 1, 7));
+        coalescer.push("accounts", account(10, 1, 6));
+        coalescer.push("accounts", account(11, 1, 8));
+        coalescer.push("pools", account(12, 1, 9));
+
+        let flushed = coalescer.flush(11);
+        assert_eq!(
+            flushed
+                .iter()
+                .map(|(_, event)| (event.slot, event.write_version))
+                .collect::<Vec<_>>(),
+            vec![(10, 3), (10, 7), (11, 8)]
+        );
+        assert!(coalescer.flush(11).is_empty());
+        assert_eq!(
+            coalescer.flush(12),
+            vec![("pools".to_owned(), account(12, 1, 9))]
+        );
+    }
+}
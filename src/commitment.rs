@@ -0,0 +1,323 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        prom::{BUFFERED_BYTES, BUFFERED_EVENTS_TOTAL, BUFFERED_SLOTS},
+        Commitment, CommitmentConfig, SlotStatus, TransactionEvent, UpdateAccountEvent,
+    },
+    prost::Message,
+    std::{
+        collections::{BTreeMap, HashSet},
+        sync::Mutex,
+    },
+};
+
+/// Items buffered per slot, with the parent of each slot to tell forks apart.
+#[derive(Debug)]
+pub struct SlotBuffer<T> {
+    slots: BTreeMap<u64, BufferedSlot<T>>,
+    size: usize,
+}
+
+#[derive(Debug)]
+struct BufferedSlot<T> {
+    parent: Option<u64>,
+    items: Vec<T>,
+    size: usize,
+}
+
+impl<T> Default for BufferedSlot<T> {
+    fn default() -> Self {
+        Self {
+            parent: None,
+            items: Vec::new(),
+            size: 0,
+        }
+    }
+}
+
+/// Slots removed from a [`SlotBuffer`] once a descendant reached a commitment.
+#[derive(Debug)]
+pub struct Settled<T> {
+    /// The slot and its buffered ancestors, oldest first.
+    pub committed: Vec<(u64, Vec<T>)>,
+    /// Buffered slots below the slot on other forks, oldest first.
+    pub abandoned: Vec<(u64, Vec<T>)>,
+}
+
+impl<T> Default for SlotBuffer<T> {
+    fn default() -> Self {
+        Self {
+            slots: BTreeMap::new(),
+            size: 0,
+        }
+    }
+}
+
+impl<T> SlotBuffer<T> {
+    /// Buffer an item of the given size in bytes.
+    pub fn push(&mut self, slot: u64, item: T, size: usize) {
+        let buffered = self.slots.entry(slot).or_default();
+        buffered.items.push(item);
+        buffered.size += size;
+        self.size += size;
+    }
+
+    pub fn set_parent(&mut self, slot: u64, parent: u64) {
+        self.slots.entry(slot).or_default().parent = Some(parent);
+    }
+
+    /// Remove the slot with its buffered ancestors, and the slots below it on other forks.
+    pub fn settle(&mut self, slot: u64) -> Settled<T> {
+        let mut committed = Vec::new();
+        let mut next = Some(slot);
+        while let Some(slot) = next {
+            let Some(buffered) = self.slots.remove(&slot) else {
+                break;
+            };
+            self.size -= buffered.size;
+            next = buffered.parent;
+            committed.push((slot, buffered.items));
+        }
+        committed.reverse();
+
+        let ancestors = committed
+            .iter()
+            .map(|(slot, _)| *slot)
+            .collect::<HashSet<_>>();
+        let forks = self
+            .slots
+            .range(..slot)
+            .map(|(slot, _)| *slot)
+            .filter(|slot| !ancestors.contains(slot))
+            .collect::<Vec<_>>();
+        let abandoned = forks
+            .into_iter()
+            .filter_map(|slot| self.remove(slot).map(|items| (slot, items)))
+            .collect();
+
+        Settled {
+            committed,
+            abandoned,
+        }
+    }
+
+    /// Remove the oldest slot.
+    pub fn pop_oldest(&mut self) -> Option<(u64, Vec<T>)> {
+        let slot = *self.slots.keys().next()?;
+        self.remove(slot).map(|items| (slot, items))
+    }
+
+    fn remove(&mut self, slot: u64) -> Option<Vec<T>> {
+        let buffered = self.slots.remove(&slot)?;
+        self.size -= buffered.size;
+        Some(buffered.items)
+    }
+
+    /// Number of buffered slots.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Total size of the buffered items in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// An event waiting for its slot to reach the commitment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BufferedEvent {
+    Account(String, Box<UpdateAccountEvent>),
+    Transaction(String, Box<TransactionEvent>),
+}
+
+impl BufferedEvent {
+    fn slot(&self) -> u64 {
+        match self {
+            Self::Account(_, event) => event.slot,
+            Self::Transaction(_, event) => event.slot,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::Account(topic, event) => topic.len() + event.encoded_len(),
+            Self::Transaction(topic, event) => topic.len() + event.encoded_len(),
+        }
+    }
+}
+
+/// Holds back account updates and transactions until their slot reaches the commitment.
+///
+/// Events of slots on abandoned forks are discarded. When the memory limits are exceeded,
+/// the oldest slots are released before reaching the commitment.
+#[derive(Debug)]
+pub struct CommitmentBuffer {
+    level: Commitment,
+    max_slots: usize,
+    max_bytes: usize,
+    slots: Mutex<SlotBuffer<BufferedEvent>>,
+}
+
+impl CommitmentBuffer {
+    pub fn new(config: &CommitmentConfig) -> Self {
+        Self {
+            level: config.level,
+            max_slots: config.max_slots,
+            max_bytes: config.max_bytes,
+            slots: Mutex::new(SlotBuffer::default()),
+        }
+    }
+
+    /// Buffer an event, returning the events to publish right away to stay within limits.
+    pub fn push(&self, event: BufferedEvent) -> Vec<BufferedEvent> {
+        let mut slots = self.slots.lock().unwrap();
+        let (slot, size) = (event.slot(), event.size());
+        slots.push(slot, event, size);
+
+        let mut released = Vec::new();
+        while slots.len() > self.max_slots.max(1)
+            || (slots.size() > self.max_bytes && slots.len() > 1)
+        {
+            let Some((_, events)) = slots.pop_oldest() else {
+                break;
+            };
+            released.extend(events);
+        }
+        BUFFERED_EVENTS_TOTAL
+            .with_label_values(&["released"])
+            .inc_by(released.len() as u64);
+        Self::update_gauges(&slots);
+        released
+    }
+
+    /// Track a slot status, returning the events of slots that reached the commitment.
+    pub fn update_slot(
+        &self,
+        slot: u64,
+        parent: Option<u64>,
+        status: SlotStatus,
+    ) -> Vec<BufferedEvent> {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some(parent) = parent {
+            slots.set_parent(slot, parent);
+        }
+        if !self.level.reached_by(status) {
+            return Vec::new();
+        }
+
+        let settled = slots.settle(slot);
+        let discarded = settled
+            .abandoned
+            .iter()
+            .map(|(_, events)| events.len())
+            .sum::<usize>();
+        let events = settled
+            .committed
+            .into_iter()
+            .flat_map(|(_, events)| events)
+            .collect::<Vec<_>>();
+        BUFFERED_EVENTS_TOTAL
+            .with_label_values(&["discarded"])
+            .inc_by(discarded as u64);
+        BUFFERED_EVENTS_TOTAL
+            .with_label_values(&["published"])
+            .inc_by(events.len() as u64);
+        Self::update_gauges(&slots);
+        events
+    }
+
+    fn update_gauges(slots: &SlotBuffer<BufferedEvent>) {
+        BUFFERED_SLOTS.set(slots.len() as i64);
+        BUFFERED_BYTES.set(slots.size() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{BufferedEvent, CommitmentBuffer, SlotBuffer},
+        crate::{Commitment, CommitmentConfig, SlotStatus, UpdateAccountEvent},
+    };
+
+    #[test]
+    fn test_slot_buffer() {
+        let mut buffer = SlotBuffer::default();
+        for (slot, parent) in [(10, 9), (11, 10), (12, 10), (13, 11), (14, 13)] {
+            buffer.set_parent(slot, parent);
+            buffer.push(slot, slot, 1);
+        }
+        assert_eq!(buffer.size(), 5);
+
+        let settled = buffer.settle(13);
+        assert_eq!(
+            settled.committed,
+            vec![(10, vec![10]), (11, vec![11]), (13, vec![13])]
+        );
+        assert_eq!(settled.abandoned, vec![(12, vec![12])]);
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.size(), 1);
+        assert_eq!(buffer.pop_oldest(), Some((14, vec![14])));
+        assert!(buffer.is_empty());
+    }
+
+    fn account(slot: u64) -> BufferedEvent {
+        BufferedEvent::Account(
+            "accounts".to_owned(),
+            Box::new(UpdateAccountEvent {
+                slot,
+                data: vec![0; 100],
+                ..UpdateAccountEvent::default()
+            }),
+        )
+    }
+
+    #[test]
+    fn test_commitment_buffer() {
+        let buffer = CommitmentBuffer::new(&CommitmentConfig {
+            level: Commitment::Rooted,
+            max_slots: 3,
+            ..CommitmentConfig::default()
+        });
+        assert!(buffer.push(account(1)).is_empty());
+        assert!(buffer
+            .update_slot(1, None, SlotStatus::Processed)
+            .is_empty());
+        assert!(buffer.push(account(2)).is_empty());
+        assert!(buffer.push(account(3)).is_empty());
+        assert!(buffer
+            .update_slot(2, Some(1), SlotStatus::Processed)
+            .is_empty());
+        assert!(buffer
+            .update_slot(3, Some(1), SlotStatus::Confirmed)
+            .is_empty());
+
+        assert_eq!(
+            buffer.update_slot(3, Some(1), SlotStatus::Rooted),
+            vec![account(1), account(3)]
+        );
+        assert!(buffer.update_slot(2, None, SlotStatus::Rooted).is_empty());
+
+        for slot in 4..=6 {
+            assert!(buffer.push(account(slot)).is_empty());
+        }
+        assert_eq!(buffer.push(account(7)), vec![account(4)]);
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        prom::DATA_COMPRESSION_BYTES_TOTAL, CompressionCodec, DataCodec, DataCompressionConfig,
+        UpdateAccountEvent,
+    },
+    log::warn,
+    lz4_flex::frame::{FrameDecoder, FrameEncoder},
+    std::io::{self, Read, Write},
+};
+
+/// Compresses the data of account updates above a size.
+pub struct DataCompressor {
+    codec: CompressionCodec,
+    min_size: usize,
+    level: i32,
+}
+
+impl DataCompressor {
+    pub fn new(config: &DataCompressionConfig) -> Self {
+        Self {
+            codec: config.codec,
+            min_size: config.min_size,
+            level: config.level,
+        }
+    }
+
+    /// Compress the data of the update, unless it is small or does not shrink.
+    pub fn compress(&self, event: &mut UpdateAccountEvent) {
+        if event.data.len() <= self.min_size || event.data_codec != DataCodec::Uncompressed as i32 {
+            return;
+        }
+        let (codec, compressed) = match self.codec {
+            CompressionCodec::Zstd => (
+                DataCodec::Zstd,
+                zstd::bulk::compress(&event.data, self.level),
+            ),
+            CompressionCodec::Lz4 => (DataCodec::Lz4, lz4_compress(&event.data)),
+        };
+        match compressed {
+            Ok(compressed) if compressed.len() < event.data.len() => {
+                DATA_COMPRESSION_BYTES_TOTAL
+                    .with_label_values(&["uncompressed"])
+                    .inc_by(event.data.len() as u64);
+                DATA_COMPRESSION_BYTES_TOTAL
+                    .with_label_values(&["compressed"])
+                    .inc_by(compressed.len() as u64);
+                event.data = compressed;
+                event.data_codec = codec.into();
+            }
+            Ok(_) => {}
+            Err(error) => warn!("Failed to compress account data: {error}"),
+        }
+    }
+}
+
+fn lz4_compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = FrameEncoder::new(Vec::new());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Restore the data of an account update published with data compression.
+pub fn decompress_data(event: &mut UpdateAccountEvent) -> io::Result<()> {
+    let data = match DataCodec::from_i32(event.data_codec) {
+        Some(DataCodec::Uncompressed) => return Ok(()),
+        Some(DataCodec::Zstd) => zstd::stream::decode_all(event.data.as_slice())?,
+        Some(DataCodec::Lz4) => {
+            let mut data = Vec::new();
+            FrameDecoder::new(event.data.as_slice()).read_to_end(&mut data)?;
+            data
+        }
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown data codec {}", event.data_codec),
+            ))
+        }
+    };
+    event.data = data;
+    event.data_codec = DataCodec::Uncompressed.into();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{decompress_data, DataCompressor},
+        crate::{CompressionCodec, DataCodec, DataCompressionConfig, UpdateAccountEvent},
+    };
+
+    fn account(data: Vec<u8>) -> UpdateAccountEvent {
+        UpdateAccountEvent {
+            data,
+            ..UpdateAccountEvent::default()
+        }
+    }
+
+    #[test]
+    fn test_data_compression() {
+        for (codec, data_codec) in [
+            (CompressionCodec::Zstd, DataCodec::Zstd),
+            (CompressionCodec::Lz4, DataCodec::Lz4),
+        ] {
+            let compressor = DataCompressor::new(&DataCompressionConfig {
+                codec,
+                min_size: 16,
+                level: 3,
+            });
+
+            let mut event = account(vec![7; 16]);
+            compressor.compress(&mut event);
+            assert_eq!(event, account(vec![7; 16]));
+
+            let mut event = account(vec![7; 4096]);
+            compressor.compress(&mut event);
+            assert_eq!(event.data_codec, data_codec as i32);
+            assert!(event.data.len() < 4096);
+            decompress_data(&mut event).unwrap();
+            assert_eq!(event, account(vec![7; 4096]));
+
+            // Data that does not shrink stays uncompressed.
+            let noise = (0..64u32)
+                .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+                .collect::<Vec<_>>();
+            let mut event = account(noise.clone());
+            compressor.compress(&mut event);
+            assert_eq!(event, account(noise));
+        }
+
+        let mut event = UpdateAccountEvent {
+            data_codec: 42,
+            ..UpdateAccountEvent::default()
+        };
+        assert!(decompress_data(&mut event).is_err());
+    }
+}
@@ -13,37 +13,97 @@
 // limitations under the License.
 
 use {
-    crate::{prom::StatsThreadedProducerContext, PrometheusService},
+    crate::{
+        prom::StatsThreadedProducerContext, retry::RetryQueue, AnchorDecoder, FileSink, KafkaSink,
+        PrometheusService, SchemaRegistry, Sink, SlotStatus, SpillBuffer, StdoutSink,
+        TransactionalSink,
+    },
+    log::{error, info},
     rdkafka::{
         config::FromClientConfigAndContext,
         error::KafkaResult,
         producer::{DefaultProducerContext, ThreadedProducer},
         ClientConfig,
     },
-    serde::Deserialize,
+    serde::{de::Error as _, Deserialize, Deserializer},
     solana_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPluginError, Result as PluginResult,
     },
-    std::{collections::HashMap, fs::File, io::Result as IoResult, net::SocketAddr, path::Path},
+    solana_program::pubkey::Pubkey,
+    std::{
+        collections::HashMap,
+        fs::File,
+        io::Result as IoResult,
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        str::FromStr,
+        sync::Arc,
+    },
 };
 
 /// Plugin config.
 #[derive(Deserialize)]
 pub struct Config {
-    /// Kafka config.
+    /// Kafka config, required for the Kafka sink.
+    #[serde(default)]
     pub kafka: HashMap<String, String>,
+    /// Output backend.
+    #[serde(default)]
+    pub sink: SinkConfig,
+    /// Send records again after failed deliveries to Kafka.
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+    /// Spill records to disk while the Kafka producer queue is full.
+    #[serde(default)]
+    pub spill: Option<SpillConfig>,
+    /// What to do with events the Kafka producer queue has no room for.
+    #[serde(default)]
+    pub on_queue_full: QueueFullPolicy,
+    /// Publish only the last update of each account per slot.
+    #[serde(default)]
+    pub coalesce_accounts: bool,
+    /// Publish changed byte ranges of account data instead of the full data.
+    #[serde(default)]
+    pub delta_encoding: Option<DeltaConfig>,
+    /// Compress the data of each account update.
+    #[serde(default)]
+    pub data_compression: Option<DataCompressionConfig>,
+    /// Publish account updates and transactions once their slot reaches a commitment.
+    #[serde(default)]
+    pub commitment: Option<CommitmentConfig>,
+    /// Publish the events of each slot in a Kafka transaction.
+    #[serde(default)]
+    pub transactions: Option<TransactionsConfig>,
     /// Graceful shutdown timeout.
     #[serde(default)]
     pub shutdown_timeout_ms: u64,
     /// Kafka topic to send account updates to.
     #[serde(default)]
     pub update_account_topic: String,
+    /// Rules routing account updates to other topics, first match wins.
+    #[serde(default)]
+    pub update_account_routes: Vec<RouteConfig>,
     /// Kafka topic to send slot status updates to.
     #[serde(default)]
     pub slot_status_topic: String,
     /// Kafka topic to send transaction to.
     #[serde(default)]
     pub transaction_topic: String,
+    /// How to publish vote transactions.
+    #[serde(default)]
+    pub vote_transactions: TransactionRoute,
+    /// How to publish failed transactions.
+    #[serde(default)]
+    pub failed_transactions: TransactionRoute,
+    /// Kafka topic to send block metadata to.
+    #[serde(default)]
+    pub block_metadata_topic: String,
+    /// Kafka topic to send ledger entries to.
+    #[serde(default)]
+    pub entry_topic: String,
+    /// Additional Kafka topic to send the end of startup marker to.
+    #[serde(default)]
+    pub startup_complete_topic: String,
     /// List of programs to ignore.
     #[serde(default)]
     pub program_ignores: Vec<String>,
@@ -53,12 +113,39 @@ pub struct Config {
     // List of accounts to include
     #[serde(default)]
     pub account_filters: Vec<String>,
+    /// Filter expression for account updates, replacing the lists above.
+    #[serde(default)]
+    pub filter: Option<FilterConfig>,
+    /// Filter for transactions, replacing the lists above for transactions when set.
+    #[serde(default)]
+    pub transaction_filter: Option<TransactionFilterConfig>,
+    /// Byte ranges of account data to publish per owner program, instead of the full data.
+    #[serde(default, deserialize_with = "deserialize_program_map")]
+    pub data_slices: HashMap<Pubkey, Vec<DataSliceConfig>>,
+    /// Attach the parsed state of SPL Token and Token-2022 accounts and mints to their updates.
+    #[serde(default)]
+    pub parse_token_accounts: bool,
+    /// Attach decoded instructions of well-known programs to transactions.
+    #[serde(default)]
+    pub decode_instructions: bool,
+    /// Attach the account keys of transactions resolved with their lookup tables.
+    #[serde(default)]
+    pub resolve_account_keys: bool,
+    /// Anchor IDL files per program, to decode its accounts and instructions.
+    #[serde(default, deserialize_with = "deserialize_program_map")]
+    pub anchor_idls: HashMap<Pubkey, PathBuf>,
     /// Publish all accounts on startup.
     #[serde(default)]
     pub publish_all_accounts: bool,
     /// Wrap all event message in a single message type.
     #[serde(default)]
     pub wrap_messages: bool,
+    /// Encoding of event messages.
+    #[serde(default)]
+    pub encoding: Encoding,
+    /// Frame protobuf payloads for Confluent Schema Registry deserializers.
+    #[serde(default)]
+    pub schema_registry: Option<SchemaRegistryConfig>,
     /// Prometheus endpoint.
     #[serde(default)]
     pub prometheus: Option<SocketAddr>,
@@ -68,15 +155,39 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             kafka: HashMap::new(),
+            sink: SinkConfig::Kafka,
+            retry: None,
+            spill: None,
+            on_queue_full: QueueFullPolicy::Drop,
+            coalesce_accounts: false,
+            delta_encoding: None,
+            data_compression: None,
+            commitment: None,
+            transactions: None,
             shutdown_timeout_ms: 30_000,
             update_account_topic: "".to_owned(),
+            update_account_routes: Vec::new(),
             slot_status_topic: "".to_owned(),
             transaction_topic: "".to_owned(),
+            vote_transactions: TransactionRoute::Publish,
+            failed_transactions: TransactionRoute::Publish,
+            block_metadata_topic: "".to_owned(),
+            entry_topic: "".to_owned(),
+            startup_complete_topic: "".to_owned(),
             program_ignores: Vec::new(),
             program_filters: Vec::new(),
             account_filters: Vec::new(),
+            filter: None,
+            transaction_filter: None,
+            data_slices: HashMap::new(),
+            parse_token_accounts: false,
+            decode_instructions: false,
+            resolve_account_keys: false,
+            anchor_idls: HashMap::new(),
             publish_all_accounts: false,
             wrap_messages: false,
+            encoding: Encoding::Protobuf,
+            schema_registry: None,
             prometheus: None,
         }
     }
@@ -93,12 +204,92 @@ impl Config {
     }
 
     /// Create rdkafka::FutureProducer from config.
-    pub fn producer(&self) -> KafkaResult<ThreadedProducer<StatsThreadedProducerContext>> {
+    pub fn producer(
+        &self,
+        retry_queue: Option<Arc<RetryQueue>>,
+        spill: Option<Arc<SpillBuffer>>,
+    ) -> KafkaResult<ThreadedProducer<StatsThreadedProducerContext>> {
         let mut config = ClientConfig::new();
         for (k, v) in self.kafka.iter() {
             config.set(k, v);
         }
-        ThreadedProducer::from_config_and_context(&config, StatsThreadedProducerContext::default())
+        if let Some(transactions) = &self.transactions {
+            config.set("transactional.id", &transactions.transactional_id);
+        }
+        ThreadedProducer::from_config_and_context(
+            &config,
+            StatsThreadedProducerContext::new(retry_queue, spill),
+        )
+    }
+
+    /// Create the output backend from config.
+    pub fn create_sink(&self) -> PluginResult<Box<dyn Sink>> {
+        Ok(match &self.sink {
+            SinkConfig::Kafka if self.transactions.is_some() => self.create_transactional_sink()?,
+            SinkConfig::Kafka => {
+                let retry_queue = self
+                    .retry
+                    .as_ref()
+                    .map(|retry| Arc::new(RetryQueue::new(retry)));
+                let spill = self
+                    .spill
+                    .as_ref()
+                    .map(|spill| {
+                        SpillBuffer::new(
+                            spill.directory.clone(),
+                            spill.max_segment_size,
+                            spill.max_size,
+                        )
+                        .map(Arc::new)
+                    })
+                    .transpose()
+                    .map_err(|error| GeyserPluginError::Custom(Box::new(error)))?;
+                let producer =
+                    self.producer(retry_queue.clone(), spill.clone())
+                        .map_err(|error| {
+                            error!("Failed to create kafka producer: {error:?}");
+                            GeyserPluginError::Custom(Box::new(error))
+                        })?;
+                info!("Created rdkafka::ThreadedProducer");
+                Box::new(
+                    KafkaSink::new(producer, retry_queue, spill)
+                        .map_err(|error| GeyserPluginError::Custom(Box::new(error)))?,
+                )
+            }
+            SinkConfig::File { .. } if self.encoding != Encoding::Protobuf => {
+                return Err(GeyserPluginError::ConfigFileReadError {
+                    msg: "the file sink requires the protobuf encoding".to_owned(),
+                });
+            }
+            SinkConfig::File {
+                directory,
+                max_file_size,
+                slots_per_file,
+            } => Box::new(
+                FileSink::new(directory.clone(), *max_file_size, *slots_per_file)
+                    .map_err(|error| GeyserPluginError::Custom(Box::new(error)))?,
+            ),
+            SinkConfig::Stdout => Box::new(StdoutSink::new(self.encoding)),
+        })
+    }
+
+    fn create_transactional_sink(&self) -> PluginResult<Box<dyn Sink>> {
+        let transactions = self.transactions.as_ref().expect("transactions are set");
+        if self.retry.is_some() || self.spill.is_some() {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "transactions can't be combined with retry or spill".to_owned(),
+            });
+        }
+        let producer = self.producer(None, None).map_err(|error| {
+            error!("Failed to create kafka producer: {error:?}");
+            GeyserPluginError::Custom(Box::new(error))
+        })?;
+        let sink = TransactionalSink::new(producer, transactions).map_err(|error| {
+            error!("Failed to initialize kafka transactions: {error:?}");
+            GeyserPluginError::Custom(Box::new(error))
+        })?;
+        info!("Created transactional rdkafka::ThreadedProducer");
+        Ok(Box::new(sink))
     }
 
     fn set_default(&mut self, k: &'static str, v: &'static str) {
@@ -108,15 +299,553 @@ impl Config {
     }
 
     fn fill_defaults(&mut self) {
+        if self.transactions.is_some() {
+            // Transactions require the idempotent producer, which requires all acks.
+            self.set_default("request.required.acks", "all");
+        }
         self.set_default("request.required.acks", "1");
         self.set_default("message.timeout.ms", "30000");
         self.set_default("compression.type", "lz4");
         self.set_default("partitioner", "murmur2_random");
     }
 
+    /// The `filter` expression, or [`Self::list_filter`] if none is set.
+    pub fn account_filter(&self) -> FilterConfig {
+        self.filter.clone().unwrap_or_else(|| self.list_filter())
+    }
+
+    /// Expression of the `program_ignores`, `program_filters` and `account_filters` lists:
+    /// accounts of programs not ignored and, if any are listed, included, or listed accounts.
+    pub fn list_filter(&self) -> FilterConfig {
+        let pubkeys = |list: &[String]| {
+            list.iter()
+                .filter_map(|p| Pubkey::from_str(p).ok())
+                .collect::<Vec<_>>()
+        };
+        let mut wants_program = vec![FilterConfig::Not(Box::new(FilterConfig::Owner(pubkeys(
+            &self.program_ignores,
+        ))))];
+        let program_filters = pubkeys(&self.program_filters);
+        if !program_filters.is_empty() {
+            wants_program.push(FilterConfig::Owner(program_filters));
+        }
+        FilterConfig::Or(vec![
+            FilterConfig::And(wants_program),
+            FilterConfig::Pubkey(pubkeys(&self.account_filters)),
+        ])
+    }
+
+    /// All topics account updates can be sent to.
+    pub fn update_account_topics(&self) -> Vec<String> {
+        let mut topics = vec![self.update_account_topic.clone()];
+        topics.extend(self.update_account_routes.iter().map(|r| r.topic.clone()));
+        topics.retain(|topic| !topic.is_empty());
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// How to publish vote transactions, dropping them if `transaction_filter` excludes them.
+    pub fn vote_transaction_route(&self) -> TransactionRoute {
+        match &self.transaction_filter {
+            Some(filter) if filter.exclude_votes => TransactionRoute::Drop,
+            _ => self.vote_transactions.clone(),
+        }
+    }
+
+    /// How to publish failed transactions, dropping them if `transaction_filter` excludes them.
+    pub fn failed_transaction_route(&self) -> TransactionRoute {
+        match &self.transaction_filter {
+            Some(filter) if filter.exclude_failed => TransactionRoute::Drop,
+            _ => self.failed_transactions.clone(),
+        }
+    }
+
+    /// All topics transactions can be sent to.
+    pub fn transaction_topics(&self) -> Vec<String> {
+        let mut topics = vec![self.transaction_topic.clone()];
+        for route in [
+            self.vote_transaction_route(),
+            self.failed_transaction_route(),
+        ] {
+            if let TransactionRoute::Topic(topic) = route {
+                topics.push(topic.clone());
+            }
+        }
+        topics.retain(|topic| !topic.is_empty());
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// All topics any event can be sent to.
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics = self.update_account_topics();
+        topics.extend(self.transaction_topics());
+        topics.extend([
+            self.slot_status_topic.clone(),
+            self.block_metadata_topic.clone(),
+            self.entry_topic.clone(),
+            self.startup_complete_topic.clone(),
+        ]);
+        topics.retain(|topic| !topic.is_empty());
+        topics.sort();
+        topics.dedup();
+        topics
+    }
+
+    /// Topics to send the end of startup marker to: every configured topic, so that the
+    /// consumers of each learn that the startup accounts are complete.
+    ///
+    /// Without `wrap_messages`, consumers of the other topics must tell the marker apart by its
+    /// `startup_complete` key.
+    pub fn startup_complete_topics(&self) -> Vec<String> {
+        self.topics()
+    }
+
+    /// Resolve schema ids for all topics, registering the schema where needed.
+    pub fn create_schema_registry(&self) -> PluginResult<Option<SchemaRegistry>> {
+        let Some(config) = &self.schema_registry else {
+            return Ok(None);
+        };
+        if self.encoding != Encoding::Protobuf {
+            return Err(GeyserPluginError::ConfigFileReadError {
+                msg: "schema_registry requires the protobuf encoding".to_owned(),
+            });
+        }
+        SchemaRegistry::new(config, &self.topics())
+            .map(Some)
+            .map_err(|error| {
+                error!("Failed to set up schema registry: {error}");
+                GeyserPluginError::Custom(error.into())
+            })
+    }
+
+    pub fn create_anchor_decoder(&self) -> PluginResult<Option<AnchorDecoder>> {
+        if self.anchor_idls.is_empty() {
+            return Ok(None);
+        }
+        AnchorDecoder::new(&self.anchor_idls)
+            .map(Some)
+            .map_err(|error| {
+                error!("Failed to load Anchor IDL: {error}");
+                GeyserPluginError::ConfigFileReadError { msg: error }
+            })
+    }
+
     pub fn create_prometheus(&self) -> IoResult<Option<PrometheusService>> {
         self.prometheus.map(PrometheusService::new).transpose()
     }
 }
 
 pub type Producer = ThreadedProducer<DefaultProducerContext>;
+
+/// Encoding of event messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// Protobuf, see `proto/event.proto`.
+    #[default]
+    Protobuf,
+    /// JSON, with base58 pubkeys, hashes and signatures and base64 data.
+    Json,
+}
+
+/// Retry queue for records Kafka failed to deliver.
+///
+/// Failed records are queued by delivery reports and sent again ahead of new records,
+/// so they can end up out of order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Maximum number of records waiting for a retry.
+    pub max_records: usize,
+    /// Give up on a record after this many failed deliveries.
+    pub max_attempts: usize,
+    /// What to drop when a record fails while the queue is full.
+    pub when_full: RetryQueueFull,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_records: 100_000,
+            max_attempts: 3,
+            when_full: RetryQueueFull::DropNewest,
+        }
+    }
+}
+
+/// Disk spill buffer for records the Kafka producer queue has no room for.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpillConfig {
+    /// Directory for spill segment files.
+    pub directory: PathBuf,
+    /// Start a new segment once this size in bytes is reached.
+    #[serde(default = "SpillConfig::default_max_segment_size")]
+    pub max_segment_size: u64,
+    /// Drop records once the spill reaches this size in bytes, zero for no limit.
+    #[serde(default)]
+    pub max_size: u64,
+}
+
+impl SpillConfig {
+    fn default_max_segment_size() -> u64 {
+        64 * 1024 * 1024
+    }
+}
+
+/// Kafka transactions per slot.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransactionsConfig {
+    /// `transactional.id` of the producer, unique per validator.
+    pub transactional_id: String,
+    /// Commit the events of a slot once it reaches this commitment.
+    #[serde(default)]
+    pub commit_on: Commitment,
+    /// Discard the events of slots on abandoned forks instead of committing them.
+    #[serde(default = "TransactionsConfig::default_abort_on_fork")]
+    pub abort_on_fork: bool,
+    /// Timeout for transaction operations.
+    #[serde(default = "TransactionsConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Maximum number of buffered slots.
+    #[serde(default = "TransactionsConfig::default_max_slots")]
+    pub max_slots: usize,
+    /// Maximum total size of buffered records in bytes.
+    #[serde(default = "TransactionsConfig::default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+impl TransactionsConfig {
+    fn default_abort_on_fork() -> bool {
+        true
+    }
+
+    fn default_timeout_ms() -> u64 {
+        10_000
+    }
+
+    fn default_max_slots() -> usize {
+        256
+    }
+
+    fn default_max_bytes() -> usize {
+        1024 * 1024 * 1024
+    }
+}
+
+/// Slot commitment level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+    /// Confirmed by a supermajority of the cluster.
+    #[default]
+    Confirmed,
+    /// Rooted on the validator.
+    Rooted,
+}
+
+impl Commitment {
+    /// Whether a slot with the status has reached this commitment.
+    pub fn reached_by(self, status: SlotStatus) -> bool {
+        match status {
+            SlotStatus::Rooted => true,
+            SlotStatus::Confirmed => self == Self::Confirmed,
+            _ => false,
+        }
+    }
+}
+
+/// Delta encoding of account data.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DeltaConfig {
+    /// Accounts with less data are always published in full.
+    pub min_data_len: usize,
+    /// Publish the full data of an account after this many deltas.
+    pub keyframe_interval: u64,
+    /// Maximum total size of the data kept to compute deltas, in bytes.
+    pub max_bytes: usize,
+}
+
+impl Default for DeltaConfig {
+    fn default() -> Self {
+        Self {
+            min_data_len: 1024,
+            keyframe_interval: 100,
+            max_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Compression of the data of each account update, on top of the Kafka batch compression.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DataCompressionConfig {
+    pub codec: CompressionCodec,
+    /// Data of this size in bytes or less is published uncompressed.
+    #[serde(default = "DataCompressionConfig::default_min_size")]
+    pub min_size: usize,
+    /// zstd compression level, ignored by lz4.
+    #[serde(default = "DataCompressionConfig::default_level")]
+    pub level: i32,
+}
+
+impl DataCompressionConfig {
+    fn default_min_size() -> usize {
+        1024
+    }
+
+    fn default_level() -> i32 {
+        3
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionCodec {
+    Zstd,
+    Lz4,
+}
+
+/// Buffering of account updates and transactions until their slot reaches a commitment.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CommitmentConfig {
+    /// Publish the events of a slot once it reaches this commitment.
+    pub level: Commitment,
+    /// Maximum number of buffered slots.
+    pub max_slots: usize,
+    /// Maximum total size of buffered events in bytes.
+    pub max_bytes: usize,
+}
+
+impl Default for CommitmentConfig {
+    fn default() -> Self {
+        Self {
+            level: Commitment::Confirmed,
+            max_slots: 256,
+            max_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Policy for events hitting a full producer queue, e.g. `"fail"` or
+/// `{"block": {"timeout_ms": 500}}`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueFullPolicy {
+    /// Drop the event and report an error to the validator.
+    #[default]
+    Drop,
+    /// Wait up to the timeout for room in the queue, then drop the event.
+    Block { timeout_ms: u64 },
+    /// Drop vote transactions and processed slot statuses, block for other events.
+    DropLowestPriority { timeout_ms: u64 },
+    /// Abort the validator process.
+    Fail,
+}
+
+/// Policy for a full retry queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryQueueFull {
+    /// Drop the record that just failed.
+    #[default]
+    DropNewest,
+    /// Drop the record that waited longest to make room.
+    DropOldest,
+}
+
+/// Output backend, e.g. `{"type": "file", "directory": "/var/lib/solana/events"}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// Publish to Kafka using the `kafka` config.
+    #[default]
+    Kafka,
+    /// Write length-delimited protobuf files, one set per topic.
+    File {
+        directory: PathBuf,
+        /// Start a new file once this size in bytes is reached, zero to disable.
+        #[serde(default)]
+        max_file_size: u64,
+        /// Start a new file every this many slots, zero to disable.
+        #[serde(default)]
+        slots_per_file: u64,
+    },
+    /// Print JSON lines to stdout.
+    Stdout,
+}
+
+/// Confluent Schema Registry settings.
+///
+/// Topics without a configured schema id get `proto/event.proto` registered under their
+/// `<topic>-value` subject at `url`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SchemaRegistryConfig {
+    /// Schema id per topic.
+    pub schema_ids: HashMap<String, u32>,
+    /// Registry URL, e.g. `http://localhost:8081`.
+    pub url: Option<String>,
+    /// Timeout for each schema registration.
+    pub timeout_ms: u64,
+}
+
+impl Default for SchemaRegistryConfig {
+    fn default() -> Self {
+        Self {
+            schema_ids: HashMap::new(),
+            url: None,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+/// Account filter expression.
+///
+/// Conditions mirror the RPC `getProgramAccounts` filters and can be combined with
+/// `and`, `or` and `not`, e.g. `{"and": [{"owner": ["..."]}, {"data_size": 165}]}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterConfig {
+    /// Account is owned by one of the programs.
+    Owner(#[serde(deserialize_with = "deserialize_pubkeys")] Vec<Pubkey>),
+    /// Account address is one of the pubkeys.
+    Pubkey(#[serde(deserialize_with = "deserialize_pubkeys")] Vec<Pubkey>),
+    /// Account data has exactly this length.
+    DataSize(usize),
+    /// Account data contains the bytes at the offset.
+    Memcmp(MemcmpConfig),
+    /// Account balance lies within the range.
+    Lamports(RangeConfig),
+    /// Account executable flag equals the value.
+    Executable(bool),
+    /// All of the expressions match.
+    And(Vec<FilterConfig>),
+    /// Any of the expressions matches.
+    Or(Vec<FilterConfig>),
+    /// The expression does not match.
+    Not(Box<FilterConfig>),
+}
+
+/// Transaction filter.
+///
+/// Transactions are included if they mention any account in `mentions` or invoke any program in
+/// `invokes`, or unconditionally if both are empty, and then narrowed down by the other options.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TransactionFilterConfig {
+    /// Include transactions using any of these accounts.
+    #[serde(deserialize_with = "deserialize_pubkeys")]
+    pub mentions: Vec<Pubkey>,
+    /// Include transactions invoking any of these programs, in top-level or inner instructions.
+    #[serde(deserialize_with = "deserialize_pubkeys")]
+    pub invokes: Vec<Pubkey>,
+    /// Only include transactions whose signers are all in this set.
+    #[serde(deserialize_with = "deserialize_pubkeys")]
+    pub signers: Vec<Pubkey>,
+    /// Drop vote transactions, overriding `vote_transactions`.
+    pub exclude_votes: bool,
+    /// Drop failed transactions, overriding `failed_transactions`.
+    pub exclude_failed: bool,
+}
+
+/// Where to publish a class of transactions, e.g. `"drop"` or `{"topic": "..."}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionRoute {
+    /// Publish to `transaction_topic`.
+    #[default]
+    Publish,
+    /// Do not publish.
+    Drop,
+    /// Publish to this topic instead of `transaction_topic`.
+    Topic(String),
+}
+
+/// Account update routing rule.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    /// Kafka topic to send matching account updates to.
+    pub topic: String,
+    /// Accounts matching this expression are routed to the topic.
+    pub filter: FilterConfig,
+}
+
+/// Compare account data at an offset, like RPC `memcmp` filters.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemcmpConfig {
+    /// Offset into the account data.
+    pub offset: usize,
+    /// Base58 encoded bytes to compare with.
+    #[serde(deserialize_with = "deserialize_base58")]
+    pub bytes: Vec<u8>,
+}
+
+/// Byte range of account data, like RPC `dataSlice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataSliceConfig {
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl<'de> Deserialize<'de> for DataSliceConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Slice {
+            offset: usize,
+            length: usize,
+        }
+
+        let Slice { offset, length } = Slice::deserialize(deserializer)?;
+        if length == 0 {
+            return Err(D::Error::custom("data slice length must not be zero"));
+        }
+        if offset.checked_add(length).is_none() {
+            return Err(D::Error::custom(format!(
+                "data slice at offset {offset} with length {length} overflows"
+            )));
+        }
+        Ok(Self { offset, length })
+    }
+}
+
+/// Inclusive range, unbounded on an omitted side.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RangeConfig {
+    #[serde(default)]
+    pub min: Option<u64>,
+    #[serde(default)]
+    pub max: Option<u64>,
+}
+
+fn deserialize_pubkeys<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Pubkey>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|p| Pubkey::from_str(p).map_err(|e| D::Error::custom(format!("{p}: {e}"))))
+        .collect()
+}
+
+fn deserialize_program_map<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<HashMap<Pubkey, T>, D::Error> {
+    HashMap::<String, T>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(program, value)| {
+            Pubkey::from_str(&program)
+                .map(|program| (program, value))
+                .map_err(|e| D::Error::custom(format!("{program}: {e}")))
+        })
+        .collect()
+}
+
+fn deserialize_base58<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let bytes = String::deserialize(deserializer)?;
+    bs58::decode(&bytes)
+        .into_vec()
+        .map_err(|e| D::Error::custom(format!("{bytes}: {e}")))
+}
@@ -0,0 +1,321 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        account_data_delta::Range,
+        prom::{DELTA_CACHED_BYTES, DELTA_UPDATES_TOTAL},
+        AccountDataDelta, DeltaConfig, UpdateAccountEvent,
+    },
+    lru::LruCache,
+    prost::Message,
+    std::{collections::HashMap, mem, sync::Mutex},
+};
+
+/// Unchanged runs up to this length are sent along with the changes around them, as each
+/// range costs a few bytes of framing.
+const MERGE_GAP: usize = 8;
+
+/// Replaces the data of account updates with the changes since the previous published update.
+///
+/// The last published data of each account is kept in a cache bounded by size, evicting the
+/// least recently updated accounts. Accounts missing from the cache, moving to another topic or
+/// due for a keyframe are published in full.
+pub struct DeltaEncoder {
+    min_data_len: usize,
+    keyframe_interval: u64,
+    max_bytes: usize,
+    state: Mutex<DeltaState>,
+}
+
+struct DeltaState {
+    accounts: LruCache<Vec<u8>, PublishedData>,
+    size: usize,
+}
+
+struct PublishedData {
+    topic: String,
+    write_version: u64,
+    data: Vec<u8>,
+    /// Deltas published since the last keyframe.
+    deltas: u64,
+}
+
+impl DeltaEncoder {
+    pub fn new(config: &DeltaConfig) -> Self {
+        Self {
+            min_data_len: config.min_data_len,
+            keyframe_interval: config.keyframe_interval,
+            max_bytes: config.max_bytes,
+            state: Mutex::new(DeltaState {
+                accounts: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    /// Replace the data of the update with a delta, if one is due and smaller than the data.
+    pub fn encode(&self, topic: &str, event: &mut UpdateAccountEvent) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        if event.data.len() < self.min_data_len {
+            if let Some(published) = state.accounts.pop(&event.pubkey) {
+                state.size -= published.data.len();
+            }
+            return;
+        }
+
+        if let Some(published) = state.accounts.get_mut(&event.pubkey) {
+            let delta = (published.topic == topic
+                && published.deltas < self.keyframe_interval
+                && published.write_version < event.write_version)
+                .then(|| AccountDataDelta {
+                    base_write_version: published.write_version,
+                    data_len: event.data.len() as u64,
+                    ranges: diff(&published.data, &event.data),
+                })
+                .filter(|delta| delta.encoded_len() < event.data.len());
+            let deltas = match delta {
+                Some(delta) => {
+                    event.data_delta = Some(delta);
+                    published.deltas + 1
+                }
+                None => 0,
+            };
+            state.size = state.size - published.data.len() + event.data.len();
+            if published.topic != topic {
+                published.topic = topic.to_owned();
+            }
+            published.write_version = event.write_version;
+            published.deltas = deltas;
+            if event.data_delta.is_some() {
+                published.data = mem::take(&mut event.data);
+            } else {
+                published.data.clone_from(&event.data);
+            }
+            let kind = if deltas > 0 { "delta" } else { "keyframe" };
+            DELTA_UPDATES_TOTAL.with_label_values(&[kind]).inc();
+        } else {
+            state.size += event.data.len();
+            state.accounts.put(
+                event.pubkey.clone(),
+                PublishedData {
+                    topic: topic.to_owned(),
+                    write_version: event.write_version,
+                    data: event.data.clone(),
+                    deltas: 0,
+                },
+            );
+            DELTA_UPDATES_TOTAL.with_label_values(&["keyframe"]).inc();
+        }
+
+        while state.size > self.max_bytes {
+            let Some((_, evicted)) = state.accounts.pop_lru() else {
+                break;
+            };
+            state.size -= evicted.data.len();
+        }
+        DELTA_CACHED_BYTES.set(state.size as i64);
+    }
+
+    /// Forget the data of an account whose update was not published, so that the next update
+    /// is published in full.
+    pub fn forget(&self, pubkey: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(published) = state.accounts.pop(pubkey) {
+            state.size -= published.data.len();
+            DELTA_CACHED_BYTES.set(state.size as i64);
+        }
+    }
+}
+
+/// Byte ranges of `new` that differ from `old`, including data appended past its end.
+fn diff(old: &[u8], new: &[u8]) -> Vec<Range> {
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    let mut push = |start: usize, end: usize| match spans.last_mut() {
+        Some(last) if start - last.1 <= MERGE_GAP => last.1 = end,
+        _ => spans.push((start, end)),
+    };
+
+    let common = old.len().min(new.len());
+    let mut offset = 0;
+    while offset < common {
+        if old[offset] == new[offset] {
+            offset += 1;
+            continue;
+        }
+        let start = offset;
+        while offset < common && old[offset] != new[offset] {
+            offset += 1;
+        }
+        push(start, offset);
+    }
+    if new.len() > common {
+        push(common, new.len());
+    }
+
+    spans
+        .into_iter()
+        .map(|(start, end)| Range {
+            offset: start as u64,
+            data: new[start..end].to_vec(),
+        })
+        .collect()
+}
+
+/// Rebuild account data from the data of the previous update and a delta.
+///
+/// Returns `None` if a range does not fit in the data length of the delta.
+pub fn apply_delta(base: &[u8], delta: &AccountDataDelta) -> Option<Vec<u8>> {
+    let mut data = base.to_vec();
+    data.resize(delta.data_len as usize, 0);
+    for range in &delta.ranges {
+        let start = usize::try_from(range.offset).ok()?;
+        let end = start.checked_add(range.data.len())?;
+        data.get_mut(start..end)?.copy_from_slice(&range.data);
+    }
+    Some(data)
+}
+
+/// Reference decoder for consumers of a topic with delta encoded account updates.
+///
+/// Keeps the data of every account seen on the topic, so it should see all updates of the
+/// topic, in order.
+#[derive(Debug, Default)]
+pub struct DeltaDecoder {
+    accounts: HashMap<Vec<u8>, (u64, Vec<u8>)>,
+}
+
+impl DeltaDecoder {
+    /// Fill in the full data of an update.
+    ///
+    /// Returns `None` for a delta against data the decoder has not seen, e.g. after a missed
+    /// update. The account can be decoded again from its next keyframe.
+    pub fn decode(&mut self, mut event: UpdateAccountEvent) -> Option<UpdateAccountEvent> {
+        if let Some(delta) = event.data_delta.take() {
+            let data = match self.accounts.get(&event.pubkey) {
+                Some((write_version, base)) if *write_version == delta.base_write_version => {
+                    apply_delta(base, &delta)
+                }
+                _ => None,
+            };
+            let Some(data) = data else {
+                self.accounts.remove(&event.pubkey);
+                return None;
+            };
+            event.data = data;
+        }
+        self.accounts.insert(
+            event.pubkey.clone(),
+            (event.write_version, event.data.clone()),
+        );
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{apply_delta, diff, DeltaDecoder, DeltaEncoder},
+        crate::{AccountDataDelta, DeltaConfig, UpdateAccountEvent},
+    };
+
+    #[test]
+    fn test_diff() {
+        let old = vec![0; 64];
+        let mut new = old.clone();
+        new[1] = 1;
+        new[5] = 1;
+        new[40] = 1;
+        new.extend_from_slice(&[2; 4]);
+
+        let ranges = diff(&old, &new);
+        assert_eq!(
+            ranges
+                .iter()
+                .map(|range| (range.offset, range.data.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 5), (40, 1), (64, 4)]
+        );
+
+        let delta = AccountDataDelta {
+            base_write_version: 0,
+            data_len: new.len() as u64,
+            ranges,
+        };
+        assert_eq!(apply_delta(&old, &delta), Some(new.clone()));
+        let shrunk = AccountDataDelta {
+            data_len: 32,
+            ..delta.clone()
+        };
+        assert_eq!(apply_delta(&old, &shrunk), None);
+        assert_eq!(
+            apply_delta(
+                &new,
+                &AccountDataDelta {
+                    data_len: 32,
+                    ranges: vec![],
+                    ..delta
+                }
+            ),
+            Some(new[..32].to_vec())
+        );
+    }
+
+    fn account(write_version: u64, byte: u8) -> UpdateAccountEvent {
+        let mut data = vec![0; 256];
+        data[100] = byte;
+        UpdateAccountEvent {
+            pubkey: vec![1; 32],
+            write_version,
+            data,
+            ..UpdateAccountEvent::default()
+        }
+    }
+
+    #[test]
+    fn test_delta_encoding() {
+        let encoder = DeltaEncoder::new(&DeltaConfig {
+            min_data_len: 128,
+            keyframe_interval: 2,
+            ..DeltaConfig::default()
+        });
+        let mut decoder = DeltaDecoder::default();
+
+        let mut kinds = vec![];
+        for write_version in 1..=5 {
+            let mut event = account(write_version, write_version as u8);
+            encoder.encode("accounts", &mut event);
+            kinds.push(event.data_delta.is_some());
+            let decoded = decoder.decode(event).unwrap();
+            assert_eq!(decoded, account(write_version, write_version as u8));
+        }
+        assert_eq!(kinds, vec![false, true, true, false, true]);
+
+        // Moving to another topic and forgetting an account both force a keyframe.
+        let mut event = account(6, 6);
+        encoder.encode("pools", &mut event);
+        assert!(event.data_delta.is_none());
+        encoder.forget(&event.pubkey);
+        let mut event = account(7, 7);
+        encoder.encode("pools", &mut event);
+        assert!(event.data_delta.is_none());
+
+        // The decoder skips deltas against data it missed.
+        let mut event = account(8, 8);
+        encoder.encode("pools", &mut event);
+        assert!(event.data_delta.is_some());
+        assert_eq!(DeltaDecoder::default().decode(event), None);
+    }
+}
@@ -13,64 +13,287 @@
 // limitations under the License.
 
 use {
-    crate::Config,
-    solana_program::pubkey::Pubkey,
-    std::{collections::HashSet, str::FromStr},
+    crate::{Config, FilterConfig, TransactionFilterConfig, TransactionRoute},
+    log::debug,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        ReplicaAccountInfoV3, ReplicaTransactionInfoV2,
+    },
+    std::collections::HashSet,
 };
 
 pub struct Filter {
-    program_ignores: HashSet<[u8; 32]>,
-    program_filters: HashSet<[u8; 32]>,
-    account_filters: HashSet<[u8; 32]>,
+    account_update: Condition,
+    /// The program and account lists, applied to every account key of transactions.
+    lists: Condition,
+    transaction: Option<TransactionCondition>,
 }
 
-impl Filter {
-    pub fn new(config: &Config) -> Self {
+/// Compiled form of [`FilterConfig`].
+enum Condition {
+    Owner(HashSet<[u8; 32]>),
+    Pubkey(HashSet<[u8; 32]>),
+    DataSize(usize),
+    Memcmp { offset: usize, bytes: Vec<u8> },
+    Lamports { min: u64, max: u64 },
+    Executable(bool),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    fn new(config: &FilterConfig) -> Self {
+        match config {
+            FilterConfig::Owner(keys) => Self::Owner(keys.iter().map(|k| k.to_bytes()).collect()),
+            FilterConfig::Pubkey(keys) => Self::Pubkey(keys.iter().map(|k| k.to_bytes()).collect()),
+            FilterConfig::DataSize(size) => Self::DataSize(*size),
+            FilterConfig::Memcmp(memcmp) => Self::Memcmp {
+                offset: memcmp.offset,
+                bytes: memcmp.bytes.clone(),
+            },
+            FilterConfig::Lamports(range) => Self::Lamports {
+                min: range.min.unwrap_or(u64::MIN),
+                max: range.max.unwrap_or(u64::MAX),
+            },
+            FilterConfig::Executable(executable) => Self::Executable(*executable),
+            FilterConfig::And(configs) => Self::And(configs.iter().map(Self::new).collect()),
+            FilterConfig::Or(configs) => Self::Or(configs.iter().map(Self::new).collect()),
+            FilterConfig::Not(config) => Self::Not(Box::new(Self::new(config))),
+        }
+    }
+
+    fn matches(&self, account: &ReplicaAccountInfoV3) -> bool {
+        match self {
+            Self::Owner(keys) => contains_key(keys, account.owner),
+            Self::Pubkey(keys) => contains_key(keys, account.pubkey),
+            Self::DataSize(size) => account.data.len() == *size,
+            Self::Memcmp { offset, bytes } => offset
+                .checked_add(bytes.len())
+                .and_then(|end| account.data.get(*offset..end))
+                .map_or(false, |data| data == bytes.as_slice()),
+            Self::Lamports { min, max } => (*min..=*max).contains(&account.lamports),
+            Self::Executable(executable) => account.executable == *executable,
+            Self::And(conditions) => conditions.iter().all(|c| c.matches(account)),
+            Self::Or(conditions) => conditions.iter().any(|c| c.matches(account)),
+            Self::Not(condition) => !condition.matches(account),
+        }
+    }
+
+    /// Whether a key taken as both the address and the owner of an account matches.
+    ///
+    /// Conditions on anything but the address and owner are met by any key.
+    fn matches_key(&self, key: &[u8]) -> bool {
+        match self {
+            Self::Owner(keys) | Self::Pubkey(keys) => contains_key(keys, key),
+            Self::DataSize(_)
+            | Self::Memcmp { .. }
+            | Self::Lamports { .. }
+            | Self::Executable(_) => true,
+            Self::And(conditions) => conditions.iter().all(|c| c.matches_key(key)),
+            Self::Or(conditions) => conditions.iter().any(|c| c.matches_key(key)),
+            Self::Not(condition) => !condition.matches_key(key),
+        }
+    }
+}
+
+/// Compiled form of [`TransactionFilterConfig`].
+struct TransactionCondition {
+    mentions: HashSet<[u8; 32]>,
+    invokes: HashSet<[u8; 32]>,
+    signers: HashSet<[u8; 32]>,
+}
+
+impl TransactionCondition {
+    fn new(config: &TransactionFilterConfig) -> Self {
         Self {
-            program_ignores: config
-                .program_ignores
-                .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
-                .collect(),
-            program_filters: config
-                .program_filters
+            mentions: config.mentions.iter().map(|k| k.to_bytes()).collect(),
+            invokes: config.invokes.iter().map(|k| k.to_bytes()).collect(),
+            signers: config.signers.iter().map(|k| k.to_bytes()).collect(),
+        }
+    }
+
+    fn matches(&self, info: &ReplicaTransactionInfoV2) -> bool {
+        let message = info.transaction.message();
+        let account_keys = message.account_keys();
+        if !self.signers.is_empty()
+            && !account_keys
                 .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
-                .collect(),
-            account_filters: config
-                .account_filters
+                .take(message.header().num_required_signatures as usize)
+                .all(|key| self.signers.contains(&key.to_bytes()))
+        {
+            return false;
+        }
+
+        if self.mentions.is_empty() && self.invokes.is_empty() {
+            return true;
+        }
+        if account_keys
+            .iter()
+            .any(|key| self.mentions.contains(&key.to_bytes()))
+        {
+            return true;
+        }
+
+        let inner_instructions = info
+            .transaction_status_meta
+            .inner_instructions
+            .iter()
+            .flatten()
+            .flat_map(|inner| inner.instructions.iter().map(|ix| &ix.instruction));
+        message
+            .instructions()
+            .iter()
+            .chain(inner_instructions)
+            .filter_map(|ix| account_keys.get(ix.program_id_index as usize))
+            .any(|program| self.invokes.contains(&program.to_bytes()))
+    }
+}
+
+fn contains_key(keys: &HashSet<[u8; 32]>, key: &[u8]) -> bool {
+    <&[u8; 32]>::try_from(key).map_or(false, |key| keys.contains(key))
+}
+
+/// Picks the topic for account updates and transactions.
+pub struct Router {
+    routes: Vec<(Condition, String)>,
+    default_topic: String,
+    transaction_topic: String,
+    vote_transactions: TransactionRoute,
+    failed_transactions: TransactionRoute,
+}
+
+impl Router {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            routes: config
+                .update_account_routes
                 .iter()
-                .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
+                .map(|route| (Condition::new(&route.filter), route.topic.clone()))
                 .collect(),
+            default_topic: config.update_account_topic.clone(),
+            transaction_topic: config.transaction_topic.clone(),
+            vote_transactions: config.vote_transaction_route(),
+            failed_transactions: config.failed_transaction_route(),
+        }
+    }
+
+    /// Topic of the first matching rule, or `update_account_topic` if none matches.
+    pub fn route(&self, account: &ReplicaAccountInfoV3) -> Option<&str> {
+        let topic = self
+            .routes
+            .iter()
+            .find(|(condition, _)| condition.matches(account))
+            .map_or(self.default_topic.as_str(), |(_, topic)| topic.as_str());
+        if topic.is_empty() {
+            debug!("No topic for account update: {:?}", account.pubkey);
+            None
+        } else {
+            Some(topic)
+        }
+    }
+
+    /// Topic for a transaction, or the reason for not publishing it.
+    pub fn route_transaction(&self, info: &ReplicaTransactionInfoV2) -> Result<&str, &'static str> {
+        let route = if info.is_vote {
+            Some((&self.vote_transactions, "vote"))
+        } else if info.transaction_status_meta.status.is_err() {
+            Some((&self.failed_transactions, "failed"))
+        } else {
+            None
+        };
+        let topic = match route {
+            None | Some((TransactionRoute::Publish, _)) => self.transaction_topic.as_str(),
+            Some((TransactionRoute::Drop, reason)) => return Err(reason),
+            Some((TransactionRoute::Topic(topic), _)) => topic.as_str(),
+        };
+        if topic.is_empty() {
+            Err("no_topic")
+        } else {
+            Ok(topic)
         }
     }
+}
 
-    pub fn wants_program(&self, program: &[u8]) -> bool {
-        match <&[u8; 32]>::try_from(program) {
-            Ok(key) => {
-                !self.program_ignores.contains(key)
-                    && (self.program_filters.is_empty() || self.program_filters.contains(key))
-            }
-            Err(_error) => true,
+impl Filter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            account_update: Condition::new(&config.account_filter()),
+            lists: Condition::new(&config.list_filter()),
+            transaction: config
+                .transaction_filter
+                .as_ref()
+                .map(TransactionCondition::new),
         }
     }
 
-    pub fn wants_account(&self, account: &[u8]) -> bool {
-        match <&[u8; 32]>::try_from(account) {
-            Ok(key) => self.account_filters.contains(key),
-            Err(_error) => true,
+    /// Whether an account update passes the account filter.
+    pub fn wants_account_update(&self, account: &ReplicaAccountInfoV3) -> bool {
+        self.account_update.matches(account)
+    }
+
+    /// Whether a transaction passes the transaction filter.
+    ///
+    /// Without `transaction_filter`, every account key of the transaction has to pass the
+    /// program and account lists, either as a program or as a listed account.
+    pub fn wants_transaction(&self, info: &ReplicaTransactionInfoV2) -> bool {
+        if let Some(transaction) = &self.transaction {
+            return transaction.matches(info);
         }
+
+        let maybe_ignored = info
+            .transaction
+            .message()
+            .account_keys()
+            .iter()
+            .find(|pubkey| !self.lists.matches_key(pubkey.as_ref()));
+        if let Some(ignored) = maybe_ignored {
+            debug!(
+                "Ignoring transaction {:?} due to account key: {:?}",
+                info.signature, ignored
+            );
+            return false;
+        }
+        true
     }
 }
 
 #[cfg(test)]
 mod tests {
     use {
-        crate::{Config, Filter},
-        solana_program::pubkey::Pubkey,
+        crate::{
+            Config, Filter, FilterConfig, MemcmpConfig, RangeConfig, RouteConfig, Router,
+            TransactionFilterConfig, TransactionRoute,
+        },
+        solana_geyser_plugin_interface::geyser_plugin_interface::{
+            ReplicaAccountInfoV3, ReplicaTransactionInfoV2,
+        },
+        solana_program::{
+            instruction::{AccountMeta, CompiledInstruction, Instruction},
+            message::Message,
+            pubkey::Pubkey,
+        },
+        solana_sdk::transaction::{SanitizedTransaction, Transaction, TransactionError},
+        solana_transaction_status::{InnerInstruction, InnerInstructions, TransactionStatusMeta},
         std::str::FromStr,
     };
 
+    fn account<'a>(pubkey: &'a [u8], owner: &'a [u8], data: &'a [u8]) -> ReplicaAccountInfoV3<'a> {
+        ReplicaAccountInfoV3 {
+            pubkey,
+            lamports: 1_000,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+            data,
+            write_version: 0,
+            txn: None,
+        }
+    }
+
+    fn pubkey(key: &str) -> Pubkey {
+        Pubkey::from_str(key).unwrap()
+    }
+
     #[test]
     fn test_filter() {
         let config = Config {
@@ -83,18 +306,18 @@ mod tests {
         };
 
         let filter = Filter::new(&config);
-        assert_eq!(filter.program_ignores.len(), 2);
+        let address = Pubkey::new_unique();
 
-        assert!(filter.wants_program(
-            &Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin")
-                .unwrap()
-                .to_bytes()
-        ));
-        assert!(!filter.wants_program(
-            &Pubkey::from_str("Vote111111111111111111111111111111111111111")
-                .unwrap()
-                .to_bytes()
-        ));
+        assert!(filter.wants_account_update(&account(
+            address.as_ref(),
+            pubkey("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").as_ref(),
+            &[]
+        )));
+        assert!(!filter.wants_account_update(&account(
+            address.as_ref(),
+            pubkey("Vote111111111111111111111111111111111111111").as_ref(),
+            &[]
+        )));
     }
 
     #[test]
@@ -109,24 +332,24 @@ mod tests {
         };
 
         let filter = Filter::new(&config);
-        assert_eq!(filter.program_ignores.len(), 2);
-
-        assert!(filter.wants_program(
-            &Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin")
-                .unwrap()
-                .to_bytes()
-        ));
-        assert!(!filter.wants_program(
-            &Pubkey::from_str("Vote111111111111111111111111111111111111111")
-                .unwrap()
-                .to_bytes()
-        ));
-
-        assert!(!filter.wants_program(
-            &Pubkey::from_str("cndy3Z4yapfJBmL3ShUp5exZKqR3z33thTzeNMm2gRZ")
-                .unwrap()
-                .to_bytes()
-        ));
+        let address = Pubkey::new_unique();
+
+        assert!(filter.wants_account_update(&account(
+            address.as_ref(),
+            pubkey("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").as_ref(),
+            &[]
+        )));
+        assert!(!filter.wants_account_update(&account(
+            address.as_ref(),
+            pubkey("Vote111111111111111111111111111111111111111").as_ref(),
+            &[]
+        )));
+
+        assert!(!filter.wants_account_update(&account(
+            address.as_ref(),
+            pubkey("cndy3Z4yapfJBmL3ShUp5exZKqR3z33thTzeNMm2gRZ").as_ref(),
+            &[]
+        )));
     }
 
     #[test]
@@ -138,27 +361,372 @@ mod tests {
         };
 
         let filter = Filter::new(&config);
-        assert_eq!(filter.program_filters.len(), 1);
-        assert_eq!(filter.account_filters.len(), 1);
-
-        println!("{:?}", filter.account_filters);
-        println!(
-            "{:?}",
-            &Pubkey::from_str("5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht")
-                .unwrap()
-                .to_bytes()
+        let address = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        assert!(filter.wants_account_update(&account(
+            address.as_ref(),
+            pubkey("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").as_ref(),
+            &[]
+        )));
+        assert!(!filter.wants_account_update(&account(address.as_ref(), owner.as_ref(), &[])));
+
+        assert!(filter.wants_account_update(&account(
+            pubkey("5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht").as_ref(),
+            owner.as_ref(),
+            &[]
+        )));
+    }
+
+    #[test]
+    fn test_account_update_lists() {
+        let config = Config {
+            program_ignores: vec!["Vote111111111111111111111111111111111111111".to_owned()],
+            account_filters: vec!["5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht".to_owned()],
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+
+        let vote = Pubkey::from_str("Vote111111111111111111111111111111111111111").unwrap();
+        let token = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF").unwrap();
+        let wanted = Pubkey::from_str("5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht").unwrap();
+        let other = Pubkey::new_unique();
+
+        assert!(filter.wants_account_update(&account(other.as_ref(), token.as_ref(), &[])));
+        assert!(!filter.wants_account_update(&account(other.as_ref(), vote.as_ref(), &[])));
+        assert!(filter.wants_account_update(&account(wanted.as_ref(), vote.as_ref(), &[])));
+    }
+
+    #[test]
+    fn test_filter_expression() {
+        let token = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF").unwrap();
+        let mint = Pubkey::new_unique();
+        let config = Config {
+            filter: Some(FilterConfig::And(vec![
+                FilterConfig::Owner(vec![token]),
+                FilterConfig::DataSize(165),
+                FilterConfig::Memcmp(MemcmpConfig {
+                    offset: 0,
+                    bytes: mint.to_bytes().to_vec(),
+                }),
+                FilterConfig::Lamports(RangeConfig {
+                    min: Some(1),
+                    max: None,
+                }),
+                FilterConfig::Not(Box::new(FilterConfig::Executable(true))),
+            ])),
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+
+        let pubkey = Pubkey::new_unique();
+        let mut data = vec![0; 165];
+        data[..32].copy_from_slice(mint.as_ref());
+        assert!(filter.wants_account_update(&account(pubkey.as_ref(), token.as_ref(), &data)));
+
+        let other_mint = Pubkey::new_unique();
+        data[..32].copy_from_slice(other_mint.as_ref());
+        assert!(!filter.wants_account_update(&account(pubkey.as_ref(), token.as_ref(), &data)));
+
+        assert!(!filter.wants_account_update(&account(pubkey.as_ref(), token.as_ref(), &[])));
+        assert!(!filter.wants_account_update(&account(
+            pubkey.as_ref(),
+            Pubkey::new_unique().as_ref(),
+            &data
+        )));
+    }
+
+    #[test]
+    fn test_filter_replaces_lists() {
+        let token = pubkey("TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF");
+        let config = Config {
+            program_ignores: vec![token.to_string()],
+            filter: Some(FilterConfig::Owner(vec![token])),
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+
+        let address = Pubkey::new_unique();
+        assert!(filter.wants_account_update(&account(address.as_ref(), token.as_ref(), &[])));
+        assert!(!filter.wants_account_update(&account(
+            address.as_ref(),
+            Pubkey::new_unique().as_ref(),
+            &[]
+        )));
+
+        // Transactions still go by the lists.
+        let tx = transaction(&Pubkey::new_unique(), &token, &[]);
+        let meta = TransactionStatusMeta::default();
+        assert!(!filter.wants_transaction(&transaction_info(&tx, &meta, false)));
+    }
+
+    #[test]
+    fn test_filter_config_json() {
+        let filter: FilterConfig = serde_json::from_str(
+            r#"{
+                "or": [
+                    {"pubkey": ["5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht"]},
+                    {"and": [
+                        {"owner": ["TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF"]},
+                        {"memcmp": {"offset": 4, "bytes": "3VNr6P"}},
+                        {"lamports": {"max": 10}}
+                    ]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let config = Config {
+            filter: Some(filter),
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+
+        let token = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF").unwrap();
+        let pubkey = Pubkey::new_unique();
+        let mut info = account(pubkey.as_ref(), token.as_ref(), b"\0\0\0\0abcd");
+        assert!(!filter.wants_account_update(&info));
+        info.lamports = 10;
+        assert!(filter.wants_account_update(&info));
+
+        assert!(serde_json::from_str::<FilterConfig>(r#"{"owner": ["not a pubkey"]}"#).is_err());
+    }
+
+    #[test]
+    fn test_router() {
+        let token = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF").unwrap();
+        let market = Pubkey::new_unique();
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            update_account_routes: vec![
+                RouteConfig {
+                    topic: "token".to_owned(),
+                    filter: FilterConfig::Owner(vec![token]),
+                },
+                RouteConfig {
+                    topic: "markets".to_owned(),
+                    filter: FilterConfig::Pubkey(vec![market]),
+                },
+            ],
+            ..Config::default()
+        };
+        let router = Router::new(&config);
+
+        let pubkey = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        assert_eq!(
+            router.route(&account(pubkey.as_ref(), token.as_ref(), &[])),
+            Some("token")
+        );
+        assert_eq!(
+            router.route(&account(market.as_ref(), owner.as_ref(), &[])),
+            Some("markets")
+        );
+        assert_eq!(
+            router.route(&account(pubkey.as_ref(), owner.as_ref(), &[])),
+            Some("accounts")
+        );
+
+        assert_eq!(
+            config.update_account_topics(),
+            vec!["accounts", "markets", "token"]
         );
 
-        assert!(filter.wants_program(
-            &Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin")
-                .unwrap()
-                .to_bytes()
-        ));
-
-        assert!(filter.wants_account(
-            &Pubkey::from_str("5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht")
-                .unwrap()
-                .to_bytes()
-        ));
+        let router = Router::new(&Config {
+            update_account_topic: "".to_owned(),
+            ..config
+        });
+        assert_eq!(
+            router.route(&account(pubkey.as_ref(), owner.as_ref(), &[])),
+            None
+        );
+    }
+
+    fn transaction(payer: &Pubkey, program: &Pubkey, accounts: &[Pubkey]) -> SanitizedTransaction {
+        let ix = Instruction::new_with_bytes(
+            *program,
+            &[],
+            accounts
+                .iter()
+                .map(|a| AccountMeta::new_readonly(*a, false))
+                .collect(),
+        );
+        let message = Message::new(&[ix], Some(payer));
+        SanitizedTransaction::from_transaction_for_tests(Transaction::new_unsigned(message))
+    }
+
+    fn transaction_info<'a>(
+        transaction: &'a SanitizedTransaction,
+        meta: &'a TransactionStatusMeta,
+        is_vote: bool,
+    ) -> ReplicaTransactionInfoV2<'a> {
+        ReplicaTransactionInfoV2 {
+            signature: transaction.signature(),
+            is_vote,
+            transaction,
+            transaction_status_meta: meta,
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn test_transaction_lists() {
+        let config = Config {
+            program_ignores: vec!["Vote111111111111111111111111111111111111111".to_owned()],
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+
+        let vote = Pubkey::from_str("Vote111111111111111111111111111111111111111").unwrap();
+        let payer = Pubkey::new_unique();
+        let meta = TransactionStatusMeta::default();
+
+        let tx = transaction(&payer, &Pubkey::new_unique(), &[]);
+        assert!(filter.wants_transaction(&transaction_info(&tx, &meta, false)));
+        let tx = transaction(&payer, &vote, &[]);
+        assert!(!filter.wants_transaction(&transaction_info(&tx, &meta, true)));
+    }
+
+    #[test]
+    fn test_transaction_filter() {
+        let payer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let inner_program = Pubkey::new_unique();
+        let mentioned = Pubkey::new_unique();
+        let config = Config {
+            program_filters: vec![Pubkey::new_unique().to_string()],
+            transaction_filter: Some(TransactionFilterConfig {
+                mentions: vec![mentioned],
+                invokes: vec![inner_program],
+                ..TransactionFilterConfig::default()
+            }),
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+        let meta = TransactionStatusMeta::default();
+
+        // Mentioned account, the program lists don't apply.
+        let tx = transaction(&payer, &program, &[mentioned]);
+        assert!(filter.wants_transaction(&transaction_info(&tx, &meta, false)));
+
+        // Program only invoked through an inner instruction.
+        let tx = transaction(&payer, &program, &[inner_program]);
+        assert!(!filter.wants_transaction(&transaction_info(&tx, &meta, false)));
+        let program_id_index = tx
+            .message()
+            .account_keys()
+            .iter()
+            .position(|key| key == &inner_program)
+            .unwrap() as u8;
+        let invoked = TransactionStatusMeta {
+            inner_instructions: Some(vec![InnerInstructions {
+                index: 0,
+                instructions: vec![InnerInstruction {
+                    instruction: CompiledInstruction::new_from_raw_parts(
+                        program_id_index,
+                        vec![],
+                        vec![],
+                    ),
+                    stack_height: Some(2),
+                }],
+            }]),
+            ..TransactionStatusMeta::default()
+        };
+        assert!(filter.wants_transaction(&transaction_info(&tx, &invoked, false)));
+    }
+
+    #[test]
+    fn test_transaction_signers() {
+        let payer = Pubkey::new_unique();
+        let config = Config {
+            transaction_filter: Some(TransactionFilterConfig {
+                signers: vec![payer],
+                ..TransactionFilterConfig::default()
+            }),
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+        let meta = TransactionStatusMeta::default();
+
+        let tx = transaction(&payer, &Pubkey::new_unique(), &[]);
+        assert!(filter.wants_transaction(&transaction_info(&tx, &meta, false)));
+        let tx = transaction(&Pubkey::new_unique(), &Pubkey::new_unique(), &[]);
+        assert!(!filter.wants_transaction(&transaction_info(&tx, &meta, false)));
+    }
+
+    #[test]
+    fn test_transaction_router() {
+        let config = Config {
+            transaction_topic: "transactions".to_owned(),
+            vote_transactions: TransactionRoute::Drop,
+            failed_transactions: TransactionRoute::Topic("failed".to_owned()),
+            ..Config::default()
+        };
+        let router = Router::new(&config);
+
+        let tx = transaction(&Pubkey::new_unique(), &Pubkey::new_unique(), &[]);
+        let meta = TransactionStatusMeta::default();
+        let failed = TransactionStatusMeta {
+            status: Err(TransactionError::AccountInUse),
+            ..TransactionStatusMeta::default()
+        };
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &meta, false)),
+            Ok("transactions")
+        );
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &failed, false)),
+            Ok("failed")
+        );
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &failed, true)),
+            Err("vote")
+        );
+        assert_eq!(config.transaction_topics(), vec!["failed", "transactions"]);
+
+        let config: Config = serde_json::from_str(
+            r#"{
+                "kafka": {},
+                "vote_transactions": {"topic": "votes"},
+                "failed_transactions": "drop"
+            }"#,
+        )
+        .unwrap();
+        let router = Router::new(&config);
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &meta, true)),
+            Ok("votes")
+        );
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &failed, false)),
+            Err("failed")
+        );
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &meta, false)),
+            Err("no_topic")
+        );
+
+        // Excluding transactions in the filter drops them like the routes do.
+        let router = Router::new(&Config {
+            transaction_topic: "transactions".to_owned(),
+            vote_transactions: TransactionRoute::Topic("votes".to_owned()),
+            transaction_filter: Some(TransactionFilterConfig {
+                exclude_votes: true,
+                exclude_failed: true,
+                ..TransactionFilterConfig::default()
+            }),
+            ..Config::default()
+        });
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &meta, true)),
+            Err("vote")
+        );
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &failed, false)),
+            Err("failed")
+        );
+        assert_eq!(
+            router.route_transaction(&transaction_info(&tx, &meta, false)),
+            Ok("transactions")
+        );
     }
 }
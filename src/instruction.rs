@@ -0,0 +1,249 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding of instructions of well-known programs, reusing the RPC `jsonParsed` parsers.
+
+use {
+    crate::{
+        parsed_instruction::Program, AnchorDecoded, AnchorDecoder, ParsedInstruction,
+        ParsedInstructionInfo,
+    },
+    serde_json::{json, Value},
+    solana_program::{
+        instruction::CompiledInstruction, message::AccountKeys, pubkey, pubkey::Pubkey,
+    },
+    solana_transaction_status::parse_instruction::parse,
+};
+
+const COMPUTE_BUDGET_PROGRAM_ID: Pubkey = pubkey!("ComputeBudget111111111111111111111111111111");
+
+/// Decodes the instructions of a transaction.
+pub struct InstructionDecoder<'a> {
+    account_keys: AccountKeys<'a>,
+    parse: bool,
+    anchor_decoder: Option<&'a AnchorDecoder>,
+}
+
+impl<'a> InstructionDecoder<'a> {
+    pub fn new(
+        account_keys: AccountKeys<'a>,
+        parse: bool,
+        anchor_decoder: Option<&'a AnchorDecoder>,
+    ) -> Self {
+        Self {
+            account_keys,
+            parse,
+            anchor_decoder,
+        }
+    }
+
+    pub fn parse(
+        &self,
+        instruction: &CompiledInstruction,
+        stack_height: Option<u32>,
+    ) -> Option<ParsedInstruction> {
+        self.parse
+            .then(|| parse_instruction(instruction, &self.account_keys, stack_height))
+            .flatten()
+    }
+
+    pub fn decode_anchor(&self, instruction: &CompiledInstruction) -> Option<AnchorDecoded> {
+        let program_id = self
+            .account_keys
+            .get(instruction.program_id_index as usize)?;
+        self.anchor_decoder?
+            .decode_instruction(program_id, &instruction.data)
+    }
+}
+
+/// Decode an instruction of the System, SPL Token, Associated Token Account, Memo, Compute
+/// Budget, Stake or Vote programs.
+pub fn parse_instruction(
+    instruction: &CompiledInstruction,
+    account_keys: &AccountKeys,
+    stack_height: Option<u32>,
+) -> Option<ParsedInstruction> {
+    let program_id = account_keys.get(instruction.program_id_index as usize)?;
+    if *program_id == COMPUTE_BUDGET_PROGRAM_ID {
+        let info = parse_compute_budget(&instruction.data)?;
+        return Some(ParsedInstruction {
+            program: Some(Program::ComputeBudget(info)),
+        });
+    }
+
+    let parsed = parse(program_id, instruction, account_keys, stack_height).ok()?;
+    let info = match parsed.parsed {
+        Value::String(memo) => ParsedInstructionInfo {
+            instruction_type: "memo".to_owned(),
+            info: Value::String(memo).to_string(),
+        },
+        Value::Object(mut parsed) => ParsedInstructionInfo {
+            instruction_type: parsed.remove("type")?.as_str()?.to_owned(),
+            info: parsed.remove("info").unwrap_or(Value::Null).to_string(),
+        },
+        _ => return None,
+    };
+    let program = match parsed.program.as_str() {
+        "system" => Program::System(info),
+        "spl-token" => Program::SplToken(info),
+        "spl-associated-token-account" => Program::SplAssociatedTokenAccount(info),
+        "spl-memo" => Program::SplMemo(info),
+        "stake" => Program::Stake(info),
+        "vote" => Program::Vote(info),
+        _ => return None,
+    };
+    Some(ParsedInstruction {
+        program: Some(program),
+    })
+}
+
+/// Decode a `solana_sdk::compute_budget::ComputeBudgetInstruction`.
+fn parse_compute_budget(data: &[u8]) -> Option<ParsedInstructionInfo> {
+    let (&tag, args) = data.split_first()?;
+    let u32_at = |offset: usize| {
+        let bytes = args.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    };
+    let (instruction_type, info) = match tag {
+        0 => (
+            "requestUnits",
+            json!({ "units": u32_at(0)?, "additionalFee": u32_at(4)? }),
+        ),
+        1 => ("requestHeapFrame", json!({ "bytes": u32_at(0)? })),
+        2 => ("setComputeUnitLimit", json!({ "units": u32_at(0)? })),
+        3 => {
+            let bytes = args.get(..8)?;
+            let micro_lamports = u64::from_le_bytes(bytes.try_into().ok()?);
+            (
+                "setComputeUnitPrice",
+                json!({ "microLamports": micro_lamports }),
+            )
+        }
+        4 => (
+            "setLoadedAccountsDataSizeLimit",
+            json!({ "bytes": u32_at(0)? }),
+        ),
+        _ => return None,
+    };
+    Some(ParsedInstructionInfo {
+        instruction_type: instruction_type.to_owned(),
+        info: info.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_instruction, InstructionDecoder, COMPUTE_BUDGET_PROGRAM_ID},
+        crate::{
+            parsed_instruction::Program, AnchorDecoded, AnchorDecoder, ParsedInstruction,
+            ParsedInstructionInfo,
+        },
+        serde_json::{json, Value},
+        solana_program::{
+            hash::hashv, instruction::CompiledInstruction, message::AccountKeys, pubkey::Pubkey,
+            system_instruction, system_program,
+        },
+        std::{collections::HashMap, fs},
+    };
+
+    #[test]
+    fn test_parse_instruction() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let keys = [from, to, system_program::id(), COMPUTE_BUDGET_PROGRAM_ID];
+        let account_keys = AccountKeys::new(&keys, None);
+
+        let transfer = system_instruction::transfer(&from, &to, 42);
+        let instruction = CompiledInstruction::new_from_raw_parts(2, transfer.data, vec![0, 1]);
+        let Some(ParsedInstruction {
+            program: Some(Program::System(info)),
+        }) = parse_instruction(&instruction, &account_keys, None)
+        else {
+            panic!("transfer not parsed");
+        };
+        assert_eq!(info.instruction_type, "transfer");
+        assert_eq!(
+            serde_json::from_str::<Value>(&info.info).unwrap(),
+            json!({
+                "source": from.to_string(),
+                "destination": to.to_string(),
+                "lamports": 42,
+            })
+        );
+
+        let mut data = vec![3];
+        data.extend_from_slice(&1_000u64.to_le_bytes());
+        let instruction = CompiledInstruction::new_from_raw_parts(3, data, vec![]);
+        assert_eq!(
+            parse_instruction(&instruction, &account_keys, None),
+            Some(ParsedInstruction {
+                program: Some(Program::ComputeBudget(ParsedInstructionInfo {
+                    instruction_type: "setComputeUnitPrice".to_owned(),
+                    info: r#"{"microLamports":1000}"#.to_owned(),
+                })),
+            })
+        );
+
+        let instruction = CompiledInstruction::new_from_raw_parts(0, vec![1, 2, 3], vec![]);
+        assert_eq!(parse_instruction(&instruction, &account_keys, None), None);
+    }
+
+    #[test]
+    fn test_decode_anchor() {
+        let program_id = Pubkey::new_unique();
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("idl.json");
+        fs::write(
+            &path,
+            r#"{
+                "instructions": [
+                    { "name": "setURI", "accounts": [], "args": [{ "name": "uri", "type": "string" }] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let anchor_decoder = AnchorDecoder::new(&HashMap::from([(program_id, path)])).unwrap();
+
+        let keys = [Pubkey::new_unique(), program_id];
+        let decoder =
+            InstructionDecoder::new(AccountKeys::new(&keys, None), false, Some(&anchor_decoder));
+        let mut data = hashv(&[b"global:set_uri"]).to_bytes()[..8].to_vec();
+        data.extend_from_slice(&[2, 0, 0, 0]);
+        data.extend_from_slice(b"ok");
+        assert_eq!(
+            decoder.decode_anchor(&CompiledInstruction::new_from_raw_parts(
+                1,
+                data.clone(),
+                vec![]
+            )),
+            Some(AnchorDecoded {
+                name: "setURI".to_owned(),
+                json: r#"{"uri":"ok"}"#.to_owned(),
+            })
+        );
+        // Instructions of other programs are not decoded.
+        assert_eq!(
+            decoder.decode_anchor(&CompiledInstruction::new_from_raw_parts(0, data, vec![])),
+            None
+        );
+        assert_eq!(
+            decoder.parse(
+                &CompiledInstruction::new_from_raw_parts(1, vec![], vec![]),
+                None
+            ),
+            None
+        );
+    }
+}
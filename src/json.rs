@@ -0,0 +1,233 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serializers for the JSON encoding of events, referenced from `build.rs`.
+
+use {
+    crate::{AccountKeySource, DataCodec, SlotStatus, TokenAccountStatus},
+    base64::{engine::general_purpose::STANDARD as BASE64, Engine},
+    serde::{ser::SerializeSeq, Serialize, Serializer},
+};
+
+pub fn base58<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&bs58::encode(bytes).into_string())
+}
+
+pub fn base58_vec<S: Serializer>(keys: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(keys.len()))?;
+    for key in keys {
+        seq.serialize_element(&bs58::encode(key).into_string())?;
+    }
+    seq.end()
+}
+
+pub fn base58_option<S: Serializer>(
+    bytes: &Option<Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match bytes {
+        Some(bytes) => base58(bytes, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64.encode(bytes))
+}
+
+pub fn slot_status<S: Serializer>(status: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+    match SlotStatus::from_i32(*status) {
+        Some(status) => serializer.serialize_str(&status.as_str_name().to_lowercase()),
+        None => serializer.serialize_i32(*status),
+    }
+}
+
+pub fn data_codec<S: Serializer>(codec: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+    match DataCodec::from_i32(*codec) {
+        Some(codec) => serializer.serialize_str(&codec.as_str_name().to_lowercase()),
+        None => serializer.serialize_i32(*codec),
+    }
+}
+
+pub fn token_account_status<S: Serializer>(state: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+    match TokenAccountStatus::from_i32(*state) {
+        Some(state) => serializer.serialize_str(&state.as_str_name().to_lowercase()),
+        None => serializer.serialize_i32(*state),
+    }
+}
+
+pub fn account_key_source<S: Serializer>(source: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+    match AccountKeySource::from_i32(*source) {
+        Some(source) => serializer.serialize_str(&source.as_str_name().to_lowercase()),
+        None => serializer.serialize_i32(*source),
+    }
+}
+
+/// Embed a JSON document held in a string.
+pub fn json<S: Serializer>(json: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    match serde_json::from_str::<serde_json::Value>(json) {
+        Ok(value) => value.serialize(serializer),
+        Err(_) => serializer.serialize_str(json),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::{
+            message_wrapper::EventMessage, sanitized_message::MessagePayload, CompiledInstruction,
+            DataCodec, LegacyLoadedMessage, LegacyMessage, MessageWrapper, SanitizedMessage,
+            SanitizedTransaction, SlotStatus, SlotStatusEvent, TransactionEvent,
+            UpdateAccountEvent,
+        },
+        serde_json::json,
+        solana_program::pubkey::Pubkey,
+        std::str::FromStr,
+    };
+
+    #[test]
+    fn test_update_account_json() {
+        let pubkey = Pubkey::from_str("5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht").unwrap();
+        let owner = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF").unwrap();
+        let event = UpdateAccountEvent {
+            slot: 100,
+            pubkey: pubkey.to_bytes().to_vec(),
+            lamports: 2_039_280,
+            owner: owner.to_bytes().to_vec(),
+            executable: false,
+            rent_epoch: 361,
+            data: b"hello".to_vec(),
+            write_version: 7,
+            txn_signature: None,
+            is_startup: true,
+            data_delta: None,
+            data_len: None,
+            data_codec: DataCodec::Uncompressed.into(),
+            token_state: None,
+            anchor: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            json!({
+                "slot": 100,
+                "pubkey": "5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht",
+                "lamports": 2_039_280,
+                "owner": "TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF",
+                "executable": false,
+                "rent_epoch": 361,
+                "data": "aGVsbG8=",
+                "write_version": 7,
+                "txn_signature": null,
+                "is_startup": true,
+                "data_delta": null,
+                "data_len": null,
+                "data_codec": "uncompressed",
+                "token_state": null,
+                "anchor": null,
+            })
+        );
+    }
+
+    #[test]
+    fn test_wrapped_slot_status_json() {
+        let wrapper = MessageWrapper {
+            event_message: Some(EventMessage::Slot(Box::new(SlotStatusEvent {
+                slot: 11,
+                parent: 10,
+                status: SlotStatus::Confirmed.into(),
+            }))),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&wrapper).unwrap(),
+            json!({
+                "event_message": {
+                    "slot": {
+                        "slot": 11,
+                        "parent": 10,
+                        "status": "confirmed",
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_transaction_json() {
+        let signature = "2AXDGYSE4f2sz7tvMMzyHvUfcoJmxudvdhBcmiUSo6ijwfYmfZYsKRxboQMPh3R4kUhXRVdtSXFXMheka4Rc4P2";
+        let payer = Pubkey::from_str("5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht").unwrap();
+        let program = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF").unwrap();
+        let event = TransactionEvent {
+            signature: vec![1; 64],
+            slot: 100,
+            transaction: Some(SanitizedTransaction {
+                message: Some(SanitizedMessage {
+                    message_payload: Some(MessagePayload::Legacy(LegacyLoadedMessage {
+                        message: Some(LegacyMessage {
+                            account_keys: vec![
+                                payer.to_bytes().to_vec(),
+                                program.to_bytes().to_vec(),
+                            ],
+                            recent_block_hash: vec![2; 32],
+                            instructions: vec![CompiledInstruction {
+                                program_id_index: 1,
+                                accounts: vec![0],
+                                data: b"hello".to_vec(),
+                                ..CompiledInstruction::default()
+                            }],
+                            ..LegacyMessage::default()
+                        }),
+                        is_writable_account_cache: vec![true, false],
+                    })),
+                }),
+                message_hash: vec![3; 32],
+                is_simple_vote_transaction: false,
+                signatures: vec![vec![1; 64]],
+            }),
+            ..TransactionEvent::default()
+        };
+
+        let value = serde_json::to_value(event).unwrap();
+        assert_eq!(value["signature"], signature);
+        let transaction = &value["transaction"];
+        assert_eq!(transaction["signatures"], json!([signature]));
+        assert_eq!(
+            transaction["message_hash"],
+            "CktRuQ2mttgRGkXJtyksdKHjUdc2C4TgDzyB98oEzy8"
+        );
+        let message = &transaction["message"]["message_payload"]["legacy"]["message"];
+        assert_eq!(
+            message["account_keys"],
+            json!([
+                "5KKsLVU6TcbVDK4BS6K1DGDxnh4Q9xjYJ8XaDCG5t8ht",
+                "TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF",
+            ])
+        );
+        assert_eq!(
+            message["recent_block_hash"],
+            "8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR"
+        );
+        assert_eq!(
+            message["instructions"],
+            json!([{
+                "program_id_index": 1,
+                "accounts": [0],
+                "data": "aGVsbG8=",
+                "parsed": null,
+                "anchor": null,
+            }])
+        );
+    }
+}
@@ -14,21 +14,52 @@
 
 use solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPlugin;
 
+mod anchor;
+mod coalesce;
+mod commitment;
+mod compression;
 mod config;
+mod delta;
 mod event;
 mod filter;
+mod instruction;
+mod json;
 mod plugin;
 mod prom;
 mod publisher;
+mod retry;
+mod schema_registry;
+mod sink;
+mod slot_tree;
+mod spill;
+mod startup;
+mod token;
+mod transactional;
 mod version;
 
 pub use {
-    config::{Config, Producer},
+    anchor::AnchorDecoder,
+    coalesce::Coalescer,
+    commitment::{BufferedEvent, CommitmentBuffer, Settled, SlotBuffer},
+    compression::{decompress_data, DataCompressor},
+    config::{
+        Commitment, CommitmentConfig, CompressionCodec, Config, DataCompressionConfig,
+        DataSliceConfig, DeltaConfig, Encoding, FilterConfig, MemcmpConfig, Producer,
+        QueueFullPolicy, RangeConfig, RetryConfig, RetryQueueFull, RouteConfig,
+        SchemaRegistryConfig, SinkConfig, SpillConfig, TransactionFilterConfig, TransactionRoute,
+        TransactionsConfig,
+    },
+    delta::{apply_delta, DeltaDecoder, DeltaEncoder},
     event::*,
-    filter::Filter,
+    filter::{Filter, Router},
     plugin::KafkaPlugin,
     prom::PrometheusService,
     publisher::Publisher,
+    retry::{RetryQueue, RetryRecord},
+    schema_registry::SchemaRegistry,
+    sink::{FileSink, KafkaSink, Record, Sink, SinkError, StdoutSink},
+    spill::SpillBuffer,
+    transactional::{TransactionalProducer, TransactionalSink},
 };
 
 #[no_mangle]
@@ -14,30 +14,48 @@
 
 use {
     crate::{
-        sanitized_message, CompiledInstruction, Config, Filter, InnerInstruction,
-        InnerInstructions, LegacyLoadedMessage, LegacyMessage, LoadedAddresses,
-        MessageAddressTableLookup, MessageHeader, PrometheusService, Publisher, Reward,
-        SanitizedMessage, SanitizedTransaction, SlotStatus, SlotStatusEvent, TransactionEvent,
-        TransactionStatusMeta, TransactionTokenBalance, UiTokenAmount, UpdateAccountEvent,
-        V0LoadedMessage, V0Message,
+        instruction::InstructionDecoder, prom::FILTERED_TRANSACTIONS_TOTAL, sanitized_message,
+        slot_tree::SlotTree, startup::StartupStats, token::parse_token_state, AccountKey,
+        AccountKeySource, AnchorDecoder, BlockMetadataEvent, BufferedEvent, Coalescer,
+        CommitmentBuffer, CompiledInstruction, Config, DataCodec, DataSliceConfig, EntryEvent,
+        Filter, InnerInstruction, InnerInstructions, LegacyLoadedMessage, LegacyMessage,
+        LoadedAddresses, MessageAddressTableLookup, MessageHeader, PrometheusService, Publisher,
+        ReturnData, Reward, Router, SanitizedMessage, SanitizedTransaction, SlotStatus,
+        SlotStatusEvent, TransactionError, TransactionEvent, TransactionStatusMeta,
+        TransactionTokenBalance, UiTokenAmount, UpdateAccountEvent, V0LoadedMessage, V0Message,
     },
-    log::{debug, error, info, log_enabled},
+    log::{debug, info, log_enabled},
     rdkafka::util::get_rdkafka_version,
     solana_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPlugin, GeyserPluginError as PluginError, ReplicaAccountInfoV3,
-        ReplicaAccountInfoVersions, ReplicaTransactionInfoV2, ReplicaTransactionInfoVersions,
+        ReplicaAccountInfoVersions, ReplicaBlockInfoV2, ReplicaBlockInfoVersions, ReplicaEntryInfo,
+        ReplicaEntryInfoVersions, ReplicaTransactionInfoV2, ReplicaTransactionInfoVersions,
         Result as PluginResult, SlotStatus as PluginSlotStatus,
     },
     solana_program::pubkey::Pubkey,
-    std::fmt::{Debug, Formatter},
+    std::{
+        collections::HashMap,
+        fmt::{Debug, Formatter},
+        sync::Mutex,
+    },
 };
 
 #[derive(Default)]
 pub struct KafkaPlugin {
     publisher: Option<Publisher>,
     filter: Option<Filter>,
+    router: Option<Router>,
+    commitment_buffer: Option<CommitmentBuffer>,
+    coalescer: Option<Coalescer>,
+    data_slices: HashMap<Pubkey, Vec<DataSliceConfig>>,
+    parse_token_accounts: bool,
+    decode_instructions: bool,
+    resolve_account_keys: bool,
+    anchor_decoder: Option<AnchorDecoder>,
     publish_all_accounts: bool,
     prometheus: Option<PrometheusService>,
+    startup_stats: StartupStats,
+    slot_tree: Mutex<SlotTree>,
 }
 
 impl Debug for KafkaPlugin {
@@ -68,18 +86,25 @@ impl GeyserPlugin for KafkaPlugin {
         let (version_n, version_s) = get_rdkafka_version();
         info!("rd_kafka_version: {:#08x}, {}", version_n, version_s);
 
-        let producer = config.producer().map_err(|error| {
-            error!("Failed to create kafka producer: {error:?}");
-            PluginError::Custom(Box::new(error))
-        })?;
-        info!("Created rdkafka::FutureProducer");
-
-        let publisher = Publisher::new(producer, &config);
+        // Everything that may fail is set up before the plugin state is assigned, so that a
+        // failed load can be retried.
+        let anchor_decoder = config.create_anchor_decoder()?;
+        let sink = config.create_sink()?;
+        let schema_registry = config.create_schema_registry()?;
+        let publisher = Publisher::new(sink, schema_registry, &config);
         let prometheus = config
             .create_prometheus()
             .map_err(|error| PluginError::Custom(Box::new(error)))?;
         self.publisher = Some(publisher);
         self.filter = Some(Filter::new(&config));
+        self.router = Some(Router::new(&config));
+        self.commitment_buffer = config.commitment.as_ref().map(CommitmentBuffer::new);
+        self.coalescer = config.coalesce_accounts.then(Coalescer::default);
+        self.data_slices = config.data_slices.clone();
+        self.parse_token_accounts = config.parse_token_accounts;
+        self.decode_instructions = config.decode_instructions;
+        self.resolve_account_keys = config.resolve_account_keys;
+        self.anchor_decoder = anchor_decoder;
         self.prometheus = prometheus;
         info!("Spawned producer");
 
@@ -89,6 +114,12 @@ impl GeyserPlugin for KafkaPlugin {
     fn on_unload(&mut self) {
         self.publisher = None;
         self.filter = None;
+        self.router = None;
+        self.commitment_buffer = None;
+        self.coalescer = None;
+        self.data_slices.clear();
+        self.anchor_decoder = None;
+        *self.slot_tree.lock().unwrap() = SlotTree::default();
         if let Some(prometheus) = self.prometheus.take() {
             prometheus.shutdown();
         }
@@ -106,11 +137,22 @@ impl GeyserPlugin for KafkaPlugin {
 
         let info = Self::unwrap_update_account(account);
         let filter = self.unwrap_filter();
-        if !filter.wants_program(info.owner) && !filter.wants_account(info.pubkey) {
+        if !filter.wants_account_update(info) {
+            if is_startup {
+                self.startup_stats.record_filtered(info.owner);
+            }
             Self::log_ignore_account_update(info);
             return Ok(());
         }
 
+        let topic = match self.unwrap_router().route(info) {
+            Some(topic) => topic,
+            None => return Ok(()),
+        };
+
+        let slices = Pubkey::try_from(info.owner)
+            .ok()
+            .and_then(|owner| self.data_slices.get(&owner));
         let event = UpdateAccountEvent {
             slot,
             pubkey: info.pubkey.to_vec(),
@@ -118,17 +160,59 @@ impl GeyserPlugin for KafkaPlugin {
             owner: info.owner.to_vec(),
             executable: info.executable,
             rent_epoch: info.rent_epoch,
-            data: info.data.to_vec(),
+            data: match slices {
+                Some(slices) => slice_data(info.data, slices),
+                None => info.data.to_vec(),
+            },
             write_version: info.write_version,
             txn_signature: info.txn.map(|v| v.signature().as_ref().to_owned()),
+            is_startup,
+            data_delta: None,
+            data_len: slices.map(|_| info.data.len() as u64),
+            data_codec: DataCodec::Uncompressed.into(),
+            token_state: self
+                .parse_token_accounts
+                .then(|| parse_token_state(info.owner, info.data))
+                .flatten(),
+            anchor: self
+                .anchor_decoder
+                .as_ref()
+                .and_then(|decoder| decoder.decode_account(info.owner, info.data)),
         };
 
-        let publisher = self.unwrap_publisher();
-        publisher
-            .update_account(event)
+        // Startup accounts are loaded from a rooted snapshot, once each.
+        if !is_startup {
+            return match &self.coalescer {
+                Some(coalescer) => {
+                    coalescer.push(topic, event);
+                    Ok(())
+                }
+                None => self.publish_account(topic, event),
+            };
+        }
+
+        self.unwrap_publisher()
+            .update_account(topic, event)
+            .map(|()| self.startup_stats.record_published(info.owner))
             .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })
     }
 
+    fn notify_end_of_startup(&self) -> PluginResult<()> {
+        if !self.publish_all_accounts {
+            return Ok(());
+        }
+
+        let event = self.startup_stats.take_event();
+        info!(
+            "End of startup: {} accounts published, {} accounts filtered",
+            event.accounts_published, event.accounts_filtered
+        );
+
+        self.unwrap_publisher()
+            .notify_end_of_startup(event)
+            .map_err(|e| PluginError::Custom(Box::new(e)))
+    }
+
     fn update_slot_status(
         &self,
         slot: u64,
@@ -136,19 +220,46 @@ impl GeyserPlugin for KafkaPlugin {
         status: PluginSlotStatus,
     ) -> PluginResult<()> {
         let publisher = self.unwrap_publisher();
-        if !publisher.wants_slot_status() {
-            return Ok(());
+        let status = SlotStatus::from(status);
+        if let Some(coalescer) = &self.coalescer {
+            let mut result = Ok(());
+            for (topic, event) in coalescer.flush(slot) {
+                let published = self.publish_account(&topic, event);
+                if result.is_ok() {
+                    result = published;
+                }
+            }
+            result?;
+        }
+        if let Some(buffer) = &self.commitment_buffer {
+            self.publish_buffered(buffer.update_slot(slot, parent, status))?;
         }
 
-        let event = SlotStatusEvent {
-            slot,
-            parent: parent.unwrap_or(0),
-            status: SlotStatus::from(status).into(),
+        let dead = {
+            let mut slot_tree = self.slot_tree.lock().unwrap();
+            slot_tree.insert(slot, parent);
+            if status == SlotStatus::Rooted {
+                slot_tree.set_root(slot)
+            } else {
+                Vec::new()
+            }
         };
 
-        publisher
-            .update_slot_status(event)
-            .map_err(|e| PluginError::SlotStatusUpdateError { msg: e.to_string() })
+        let events = std::iter::once((slot, parent, status)).chain(
+            dead.into_iter()
+                .map(|(slot, parent)| (slot, parent, SlotStatus::Dead)),
+        );
+        for (slot, parent, status) in events {
+            let event = SlotStatusEvent {
+                slot,
+                parent: parent.unwrap_or(0),
+                status: status.into(),
+            };
+            publisher
+                .update_slot_status(event)
+                .map_err(|e| PluginError::SlotStatusUpdateError { msg: e.to_string() })?;
+        }
+        Ok(())
     }
 
     fn notify_transaction(
@@ -164,29 +275,83 @@ impl GeyserPlugin for KafkaPlugin {
         let filter = self.unwrap_filter();
         let info = Self::unwrap_transaction(transaction);
 
-        let maybe_ignored = info
-            .transaction
-            .message()
-            .account_keys()
-            .iter()
-            .find(|pubkey| {
-                !(filter.wants_program(pubkey.as_ref()) || filter.wants_account(pubkey.as_ref()))
-            });
-        if let Some(ignored) = maybe_ignored {
-            debug!(
-                "Ignoring transaction {:?} due to account key: {:?}",
-                info.signature, ignored
-            );
+        // Routing first counts dropped vote and failed transactions by their reason.
+        let topic = match self.unwrap_router().route_transaction(info) {
+            Ok(topic) => topic,
+            Err(reason) => {
+                FILTERED_TRANSACTIONS_TOTAL
+                    .with_label_values(&[reason])
+                    .inc();
+                return Ok(());
+            }
+        };
+
+        if !filter.wants_transaction(info) {
+            debug!("Ignoring transaction {:?}", info.signature);
+            FILTERED_TRANSACTIONS_TOTAL
+                .with_label_values(&["filter"])
+                .inc();
             return Ok(());
         }
 
-        let event = Self::build_transaction_event(slot, info);
+        let event = self.build_transaction_event(slot, info);
+
+        if let Some(buffer) = &self.commitment_buffer {
+            let released = buffer.push(BufferedEvent::Transaction(
+                topic.to_owned(),
+                Box::new(event),
+            ));
+            return self.publish_buffered(released);
+        }
 
         publisher
-            .update_transaction(event)
+            .update_transaction(topic, event)
             .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() })
     }
 
+    fn notify_entry(&self, entry: ReplicaEntryInfoVersions) -> PluginResult<()> {
+        let publisher = self.unwrap_publisher();
+        if !publisher.wants_entry() {
+            return Ok(());
+        }
+
+        let info = Self::unwrap_entry(entry);
+        let event = EntryEvent {
+            slot: info.slot,
+            index: info.index as u64,
+            num_hashes: info.num_hashes,
+            hash: info.hash.to_vec(),
+            executed_transaction_count: info.executed_transaction_count,
+        };
+
+        publisher
+            .update_entry(event)
+            .map_err(|e| PluginError::Custom(Box::new(e)))
+    }
+
+    fn notify_block_metadata(&self, blockinfo: ReplicaBlockInfoVersions) -> PluginResult<()> {
+        let publisher = self.unwrap_publisher();
+        if !publisher.wants_block_metadata() {
+            return Ok(());
+        }
+
+        let info = Self::unwrap_block_metadata(blockinfo);
+        let event = BlockMetadataEvent {
+            slot: info.slot,
+            blockhash: info.blockhash.to_owned(),
+            parent_slot: info.parent_slot,
+            parent_blockhash: info.parent_blockhash.to_owned(),
+            rewards: info.rewards.iter().map(Self::build_reward).collect(),
+            block_time: info.block_time,
+            block_height: info.block_height,
+            executed_transaction_count: info.executed_transaction_count,
+        };
+
+        publisher
+            .update_block_metadata(event)
+            .map_err(|e| PluginError::Custom(Box::new(e)))
+    }
+
     fn account_data_notifications_enabled(&self) -> bool {
         self.unwrap_publisher().wants_update_account()
     }
@@ -194,6 +359,10 @@ impl GeyserPlugin for KafkaPlugin {
     fn transaction_notifications_enabled(&self) -> bool {
         self.unwrap_publisher().wants_transaction()
     }
+
+    fn entry_notifications_enabled(&self) -> bool {
+        self.unwrap_publisher().wants_entry()
+    }
 }
 
 impl KafkaPlugin {
@@ -201,6 +370,37 @@ impl KafkaPlugin {
         Default::default()
     }
 
+    /// Publish an account update, unless the commitment buffer holds it back.
+    fn publish_account(&self, topic: &str, event: UpdateAccountEvent) -> PluginResult<()> {
+        if let Some(buffer) = &self.commitment_buffer {
+            let released = buffer.push(BufferedEvent::Account(topic.to_owned(), Box::new(event)));
+            return self.publish_buffered(released);
+        }
+        self.unwrap_publisher()
+            .update_account(topic, event)
+            .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })
+    }
+
+    /// Publish events released by the commitment buffer, reporting the first error.
+    fn publish_buffered(&self, events: Vec<BufferedEvent>) -> PluginResult<()> {
+        let publisher = self.unwrap_publisher();
+        let mut result = Ok(());
+        for event in events {
+            let published = match event {
+                BufferedEvent::Account(topic, event) => publisher
+                    .update_account(&topic, *event)
+                    .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() }),
+                BufferedEvent::Transaction(topic, event) => publisher
+                    .update_transaction(&topic, *event)
+                    .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() }),
+            };
+            if result.is_ok() {
+                result = published;
+            }
+        }
+        result
+    }
+
     fn unwrap_publisher(&self) -> &Publisher {
         self.publisher.as_ref().expect("publisher is unavailable")
     }
@@ -209,6 +409,10 @@ impl KafkaPlugin {
         self.filter.as_ref().expect("filter is unavailable")
     }
 
+    fn unwrap_router(&self) -> &Router {
+        self.router.as_ref().expect("router is unavailable")
+    }
+
     fn unwrap_update_account(account: ReplicaAccountInfoVersions) -> &ReplicaAccountInfoV3 {
         match account {
             ReplicaAccountInfoVersions::V0_0_1(_info) => {
@@ -232,25 +436,99 @@ impl KafkaPlugin {
         }
     }
 
+    fn unwrap_entry(entry: ReplicaEntryInfoVersions) -> &ReplicaEntryInfo {
+        match entry {
+            ReplicaEntryInfoVersions::V0_0_1(info) => info,
+        }
+    }
+
+    fn unwrap_block_metadata(blockinfo: ReplicaBlockInfoVersions) -> &ReplicaBlockInfoV2 {
+        match blockinfo {
+            ReplicaBlockInfoVersions::V0_0_1(_info) => {
+                panic!("ReplicaBlockInfoVersions::V0_0_1 unsupported, please upgrade your Solana node.");
+            }
+            ReplicaBlockInfoVersions::V0_0_2(info) => info,
+        }
+    }
+
     fn build_compiled_instruction(
         ix: &solana_program::instruction::CompiledInstruction,
+        decoder: Option<&InstructionDecoder>,
+        stack_height: Option<u32>,
     ) -> CompiledInstruction {
         CompiledInstruction {
             program_id_index: ix.program_id_index as u32,
             accounts: ix.clone().accounts.into_iter().map(|v| v as u32).collect(),
             data: ix.data.clone(),
+            parsed: decoder.and_then(|decoder| decoder.parse(ix, stack_height)),
+            anchor: decoder.and_then(|decoder| decoder.decode_anchor(ix)),
         }
     }
 
     fn build_inner_instruction(
         ix: &solana_transaction_status::InnerInstruction,
+        decoder: Option<&InstructionDecoder>,
     ) -> InnerInstruction {
         InnerInstruction {
-            instruction: Some(Self::build_compiled_instruction(&ix.instruction)),
+            instruction: Some(Self::build_compiled_instruction(
+                &ix.instruction,
+                decoder,
+                ix.stack_height,
+            )),
             stack_height: ix.stack_height,
         }
     }
 
+    /// List the account keys of the message with their flags and source.
+    fn build_account_keys(message: &solana_program::message::SanitizedMessage) -> Vec<AccountKey> {
+        let num_static_keys = match message {
+            solana_program::message::SanitizedMessage::Legacy(lv) => lv.message.account_keys.len(),
+            solana_program::message::SanitizedMessage::V0(v0) => v0.message.account_keys.len(),
+        };
+        message
+            .account_keys()
+            .iter()
+            .enumerate()
+            .map(|(i, pubkey)| AccountKey {
+                pubkey: pubkey.as_ref().into(),
+                is_signer: message.is_signer(i),
+                is_writable: message.is_writable(i),
+                source: if i < num_static_keys {
+                    AccountKeySource::Static
+                } else {
+                    AccountKeySource::Loaded
+                }
+                .into(),
+            })
+            .collect()
+    }
+
+    fn build_transaction_error(
+        error: &solana_sdk::transaction::TransactionError,
+    ) -> TransactionError {
+        use solana_sdk::{instruction::InstructionError, transaction::TransactionError as Error};
+
+        let (instruction_index, instruction_error, account_index) = match error {
+            Error::InstructionError(index, error) => (Some(*index as u32), Some(error), None),
+            Error::DuplicateInstruction(index) => (Some(*index as u32), None, None),
+            Error::InsufficientFundsForRent { account_index } => {
+                (None, None, Some(*account_index as u32))
+            }
+            _ => (None, None, None),
+        };
+        TransactionError {
+            kind: transaction_error_kind(error).to_owned(),
+            instruction_index,
+            instruction_error: instruction_error
+                .map(|error| instruction_error_kind(error).to_owned()),
+            custom_error_code: match instruction_error {
+                Some(InstructionError::Custom(code)) => Some(*code),
+                _ => None,
+            },
+            account_index,
+        }
+    }
+
     fn build_message_header(header: &solana_program::message::MessageHeader) -> MessageHeader {
         MessageHeader {
             num_required_signatures: header.num_required_signatures as u32,
@@ -259,6 +537,22 @@ impl KafkaPlugin {
         }
     }
 
+    fn build_reward(reward: &solana_transaction_status::Reward) -> Reward {
+        Reward {
+            pubkey: reward.pubkey.clone(),
+            lamports: reward.lamports,
+            post_balance: reward.post_balance,
+            reward_type: match reward.reward_type {
+                Some(r) => r as i32,
+                None => 0,
+            },
+            commission: match reward.commission {
+                Some(v) => v as u32,
+                None => 0,
+            },
+        }
+    }
+
     fn build_transaction_token_balance(
         transaction_token_account_balance: solana_transaction_status::TransactionTokenBalance,
     ) -> TransactionTokenBalance {
@@ -278,6 +572,7 @@ impl KafkaPlugin {
     }
 
     fn build_transaction_event(
+        &self,
         slot: u64,
         ReplicaTransactionInfoV2 {
             signature,
@@ -287,35 +582,64 @@ impl KafkaPlugin {
             index,
         }: &ReplicaTransactionInfoV2,
     ) -> TransactionEvent {
+        let anchor_decoder = self.anchor_decoder.as_ref();
+        let decoder = (self.decode_instructions || anchor_decoder.is_some()).then(|| {
+            InstructionDecoder::new(
+                transaction.message().account_keys(),
+                self.decode_instructions,
+                anchor_decoder,
+            )
+        });
+        let decoder = decoder.as_ref();
         TransactionEvent {
             is_vote: *is_vote,
             slot,
             index: *index as u64,
             signature: signature.as_ref().into(),
+            account_keys: if self.resolve_account_keys {
+                Self::build_account_keys(transaction.message())
+            } else {
+                Vec::new()
+            },
             transaction_status_meta: Some(TransactionStatusMeta {
                 is_status_err: transaction_status_meta.status.is_err(),
                 error_info: match &transaction_status_meta.status {
                     Err(e) => e.to_string(),
                     Ok(_) => "".to_owned(),
                 },
+                error: transaction_status_meta
+                    .status
+                    .as_ref()
+                    .err()
+                    .map(Self::build_transaction_error),
+                loaded_addresses: Some(LoadedAddresses {
+                    writable: transaction_status_meta
+                        .loaded_addresses
+                        .writable
+                        .iter()
+                        .map(|x| x.as_ref().into())
+                        .collect(),
+                    readonly: transaction_status_meta
+                        .loaded_addresses
+                        .readonly
+                        .iter()
+                        .map(|x| x.as_ref().into())
+                        .collect(),
+                }),
+                return_data: transaction_status_meta
+                    .return_data
+                    .as_ref()
+                    .map(|return_data| ReturnData {
+                        program_id: return_data.program_id.as_ref().into(),
+                        data: return_data.data.clone(),
+                    }),
+                compute_units_consumed: transaction_status_meta.compute_units_consumed,
                 rewards: transaction_status_meta
                     .rewards
-                    .clone()
+                    .as_ref()
                     .unwrap()
-                    .into_iter()
-                    .map(|x| Reward {
-                        pubkey: x.pubkey,
-                        lamports: x.lamports,
-                        post_balance: x.post_balance,
-                        reward_type: match x.reward_type {
-                            Some(r) => r as i32,
-                            None => 0,
-                        },
-                        commission: match x.commission {
-                            Some(v) => v as u32,
-                            None => 0,
-                        },
-                    })
+                    .iter()
+                    .map(Self::build_reward)
                     .collect(),
                 fee: transaction_status_meta.fee,
                 log_messages: match &transaction_status_meta.log_messages {
@@ -331,7 +655,7 @@ impl KafkaPlugin {
                             instructions: inner
                                 .instructions
                                 .iter()
-                                .map(Self::build_inner_instruction)
+                                .map(|ix| Self::build_inner_instruction(ix, decoder))
                                 .collect(),
                         })
                         .collect(),
@@ -376,7 +700,9 @@ impl KafkaPlugin {
                                         .message
                                         .instructions
                                         .iter()
-                                        .map(Self::build_compiled_instruction)
+                                        .map(|ix| {
+                                            Self::build_compiled_instruction(ix, decoder, None)
+                                        })
                                         .collect(),
                                     recent_block_hash: lv.message.recent_blockhash.as_ref().into(),
                                 }),
@@ -401,7 +727,9 @@ impl KafkaPlugin {
                                         .message
                                         .instructions
                                         .iter()
-                                        .map(Self::build_compiled_instruction)
+                                        .map(|ix| {
+                                            Self::build_compiled_instruction(ix, decoder, None)
+                                        })
                                         .collect(),
                                     address_table_lookup: v0
                                         .message
@@ -469,3 +797,324 @@ impl KafkaPlugin {
         }
     }
 }
+
+/// The name of a transaction error, matched explicitly so that it doesn't depend on `Debug`.
+fn transaction_error_kind(error: &solana_sdk::transaction::TransactionError) -> &'static str {
+    use solana_sdk::transaction::TransactionError as Error;
+
+    match error {
+        Error::AccountInUse => "AccountInUse",
+        Error::AccountLoadedTwice => "AccountLoadedTwice",
+        Error::AccountNotFound => "AccountNotFound",
+        Error::ProgramAccountNotFound => "ProgramAccountNotFound",
+        Error::InsufficientFundsForFee => "InsufficientFundsForFee",
+        Error::InvalidAccountForFee => "InvalidAccountForFee",
+        Error::AlreadyProcessed => "AlreadyProcessed",
+        Error::BlockhashNotFound => "BlockhashNotFound",
+        Error::InstructionError(..) => "InstructionError",
+        Error::CallChainTooDeep => "CallChainTooDeep",
+        Error::MissingSignatureForFee => "MissingSignatureForFee",
+        Error::InvalidAccountIndex => "InvalidAccountIndex",
+        Error::SignatureFailure => "SignatureFailure",
+        Error::InvalidProgramForExecution => "InvalidProgramForExecution",
+        Error::SanitizeFailure => "SanitizeFailure",
+        Error::ClusterMaintenance => "ClusterMaintenance",
+        Error::AccountBorrowOutstanding => "AccountBorrowOutstanding",
+        Error::WouldExceedMaxBlockCostLimit => "WouldExceedMaxBlockCostLimit",
+        Error::UnsupportedVersion => "UnsupportedVersion",
+        Error::InvalidWritableAccount => "InvalidWritableAccount",
+        Error::WouldExceedMaxAccountCostLimit => "WouldExceedMaxAccountCostLimit",
+        Error::WouldExceedAccountDataBlockLimit => "WouldExceedAccountDataBlockLimit",
+        Error::TooManyAccountLocks => "TooManyAccountLocks",
+        Error::AddressLookupTableNotFound => "AddressLookupTableNotFound",
+        Error::InvalidAddressLookupTableOwner => "InvalidAddressLookupTableOwner",
+        Error::InvalidAddressLookupTableData => "InvalidAddressLookupTableData",
+        Error::InvalidAddressLookupTableIndex => "InvalidAddressLookupTableIndex",
+        Error::InvalidRentPayingAccount => "InvalidRentPayingAccount",
+        Error::WouldExceedMaxVoteCostLimit => "WouldExceedMaxVoteCostLimit",
+        Error::WouldExceedAccountDataTotalLimit => "WouldExceedAccountDataTotalLimit",
+        Error::DuplicateInstruction(_) => "DuplicateInstruction",
+        Error::InsufficientFundsForRent { .. } => "InsufficientFundsForRent",
+        Error::MaxLoadedAccountsDataSizeExceeded => "MaxLoadedAccountsDataSizeExceeded",
+        Error::InvalidLoadedAccountsDataSizeLimit => "InvalidLoadedAccountsDataSizeLimit",
+        Error::ResanitizationNeeded => "ResanitizationNeeded",
+        Error::UnbalancedTransaction => "UnbalancedTransaction",
+    }
+}
+
+/// The name of an instruction error, matched explicitly so that it doesn't depend on `Debug`.
+fn instruction_error_kind(error: &solana_sdk::instruction::InstructionError) -> &'static str {
+    use solana_sdk::instruction::InstructionError as Error;
+
+    match error {
+        Error::GenericError => "GenericError",
+        Error::InvalidArgument => "InvalidArgument",
+        Error::InvalidInstructionData => "InvalidInstructionData",
+        Error::InvalidAccountData => "InvalidAccountData",
+        Error::AccountDataTooSmall => "AccountDataTooSmall",
+        Error::InsufficientFunds => "InsufficientFunds",
+        Error::IncorrectProgramId => "IncorrectProgramId",
+        Error::MissingRequiredSignature => "MissingRequiredSignature",
+        Error::AccountAlreadyInitialized => "AccountAlreadyInitialized",
+        Error::UninitializedAccount => "UninitializedAccount",
+        Error::UnbalancedInstruction => "UnbalancedInstruction",
+        Error::ModifiedProgramId => "ModifiedProgramId",
+        Error::ExternalAccountLamportSpend => "ExternalAccountLamportSpend",
+        Error::ExternalAccountDataModified => "ExternalAccountDataModified",
+        Error::ReadonlyLamportChange => "ReadonlyLamportChange",
+        Error::ReadonlyDataModified => "ReadonlyDataModified",
+        Error::DuplicateAccountIndex => "DuplicateAccountIndex",
+        Error::ExecutableModified => "ExecutableModified",
+        Error::RentEpochModified => "RentEpochModified",
+        Error::NotEnoughAccountKeys => "NotEnoughAccountKeys",
+        Error::AccountDataSizeChanged => "AccountDataSizeChanged",
+        Error::AccountNotExecutable => "AccountNotExecutable",
+        Error::AccountBorrowFailed => "AccountBorrowFailed",
+        Error::AccountBorrowOutstanding => "AccountBorrowOutstanding",
+        Error::DuplicateAccountOutOfSync => "DuplicateAccountOutOfSync",
+        Error::Custom(_) => "Custom",
+        Error::InvalidError => "InvalidError",
+        Error::ExecutableDataModified => "ExecutableDataModified",
+        Error::ExecutableLamportChange => "ExecutableLamportChange",
+        Error::ExecutableAccountNotRentExempt => "ExecutableAccountNotRentExempt",
+        Error::UnsupportedProgramId => "UnsupportedProgramId",
+        Error::CallDepth => "CallDepth",
+        Error::MissingAccount => "MissingAccount",
+        Error::ReentrancyNotAllowed => "ReentrancyNotAllowed",
+        Error::MaxSeedLengthExceeded => "MaxSeedLengthExceeded",
+        Error::InvalidSeeds => "InvalidSeeds",
+        Error::InvalidRealloc => "InvalidRealloc",
+        Error::ComputationalBudgetExceeded => "ComputationalBudgetExceeded",
+        Error::PrivilegeEscalation => "PrivilegeEscalation",
+        Error::ProgramEnvironmentSetupFailure => "ProgramEnvironmentSetupFailure",
+        Error::ProgramFailedToComplete => "ProgramFailedToComplete",
+        Error::ProgramFailedToCompile => "ProgramFailedToCompile",
+        Error::Immutable => "Immutable",
+        Error::IncorrectAuthority => "IncorrectAuthority",
+        Error::BorshIoError(_) => "BorshIoError",
+        Error::AccountNotRentExempt => "AccountNotRentExempt",
+        Error::InvalidAccountOwner => "InvalidAccountOwner",
+        Error::ArithmeticOverflow => "ArithmeticOverflow",
+        Error::UnsupportedSysvar => "UnsupportedSysvar",
+        Error::IllegalOwner => "IllegalOwner",
+        Error::MaxAccountsDataAllocationsExceeded => "MaxAccountsDataAllocationsExceeded",
+        Error::MaxAccountsExceeded => "MaxAccountsExceeded",
+        Error::MaxInstructionTraceLengthExceeded => "MaxInstructionTraceLengthExceeded",
+        Error::BuiltinProgramsMustConsumeComputeUnits => "BuiltinProgramsMustConsumeComputeUnits",
+    }
+}
+
+/// Concatenate the slices of account data, cut short at the end of the data like RPC `dataSlice`.
+fn slice_data(data: &[u8], slices: &[DataSliceConfig]) -> Vec<u8> {
+    let capacity = slices
+        .iter()
+        .map(|slice| slice.length.min(data.len()))
+        .fold(0, usize::saturating_add);
+    let mut sliced = Vec::with_capacity(capacity);
+    for slice in slices {
+        let start = slice.offset.min(data.len());
+        let end = slice.offset.saturating_add(slice.length).min(data.len());
+        sliced.extend_from_slice(&data[start..end]);
+    }
+    sliced
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{slice_data, KafkaPlugin},
+        crate::{AccountKey, AccountKeySource, DataSliceConfig, TransactionError},
+        solana_program::message::{
+            v0::{self, LoadedAddresses, LoadedMessage, MessageAddressTableLookup},
+            LegacyMessage, Message, MessageHeader, SanitizedMessage,
+        },
+        solana_sdk::{
+            hash::Hash,
+            instruction::{CompiledInstruction, InstructionError},
+            pubkey::Pubkey,
+            transaction::TransactionError as Error,
+        },
+    };
+
+    fn account_key(pubkey: Pubkey, is_signer: bool, is_writable: bool, loaded: bool) -> AccountKey {
+        AccountKey {
+            pubkey: pubkey.to_bytes().to_vec(),
+            is_signer,
+            is_writable,
+            source: if loaded {
+                AccountKeySource::Loaded
+            } else {
+                AccountKeySource::Static
+            }
+            .into(),
+        }
+    }
+
+    #[test]
+    fn test_slice_data() {
+        let data = (0..10).collect::<Vec<u8>>();
+        let slices = [
+            DataSliceConfig {
+                offset: 8,
+                length: 4,
+            },
+            DataSliceConfig {
+                offset: 1,
+                length: 2,
+            },
+            DataSliceConfig {
+                offset: 20,
+                length: 1,
+            },
+        ];
+        assert_eq!(slice_data(&data, &slices), vec![8, 9, 1, 2]);
+
+        let slices = [DataSliceConfig {
+            offset: 2,
+            length: usize::MAX - 2,
+        }];
+        assert_eq!(slice_data(&data, &slices), (2..10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_data_slice_config() {
+        assert_eq!(
+            serde_json::from_str::<DataSliceConfig>(r#"{"offset": 1, "length": 2}"#).unwrap(),
+            DataSliceConfig {
+                offset: 1,
+                length: 2,
+            }
+        );
+        assert!(serde_json::from_str::<DataSliceConfig>(r#"{"offset": 1, "length": 0}"#).is_err());
+        let overflowing = format!(r#"{{"offset": 1, "length": {}}}"#, usize::MAX);
+        assert!(serde_json::from_str::<DataSliceConfig>(&overflowing).is_err());
+    }
+
+    #[test]
+    fn test_build_account_keys_legacy() {
+        let (payer, account, program) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let message =
+            SanitizedMessage::Legacy(LegacyMessage::new(Message::new_with_compiled_instructions(
+                1,
+                0,
+                1,
+                vec![payer, account, program],
+                Hash::default(),
+                vec![CompiledInstruction::new_from_raw_parts(
+                    2,
+                    vec![],
+                    vec![0, 1],
+                )],
+            )));
+
+        assert_eq!(
+            KafkaPlugin::build_account_keys(&message),
+            vec![
+                account_key(payer, true, true, false),
+                account_key(account, false, true, false),
+                account_key(program, false, false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_account_keys_v0() {
+        let (payer, program, table, writable, readonly) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let message = SanitizedMessage::V0(LoadedMessage::new(
+            v0::Message {
+                header: MessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 1,
+                },
+                account_keys: vec![payer, program],
+                recent_blockhash: Hash::default(),
+                instructions: vec![CompiledInstruction::new_from_raw_parts(
+                    1,
+                    vec![],
+                    vec![0, 2, 3],
+                )],
+                address_table_lookups: vec![MessageAddressTableLookup {
+                    account_key: table,
+                    writable_indexes: vec![4],
+                    readonly_indexes: vec![7],
+                }],
+            },
+            LoadedAddresses {
+                writable: vec![writable],
+                readonly: vec![readonly],
+            },
+        ));
+
+        assert_eq!(
+            KafkaPlugin::build_account_keys(&message),
+            vec![
+                account_key(payer, true, true, false),
+                account_key(program, false, false, false),
+                account_key(writable, false, true, true),
+                account_key(readonly, false, false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_transaction_error() {
+        assert_eq!(
+            KafkaPlugin::build_transaction_error(&Error::InstructionError(
+                2,
+                InstructionError::Custom(6001)
+            )),
+            TransactionError {
+                kind: "InstructionError".to_owned(),
+                instruction_index: Some(2),
+                instruction_error: Some("Custom".to_owned()),
+                custom_error_code: Some(6001),
+                account_index: None,
+            }
+        );
+        assert_eq!(
+            KafkaPlugin::build_transaction_error(&Error::InsufficientFundsForRent {
+                account_index: 1
+            }),
+            TransactionError {
+                kind: "InsufficientFundsForRent".to_owned(),
+                account_index: Some(1),
+                ..TransactionError::default()
+            }
+        );
+        assert_eq!(
+            KafkaPlugin::build_transaction_error(&Error::InstructionError(
+                0,
+                InstructionError::BorshIoError("unexpected end".to_owned())
+            )),
+            TransactionError {
+                kind: "InstructionError".to_owned(),
+                instruction_index: Some(0),
+                instruction_error: Some("BorshIoError".to_owned()),
+                ..TransactionError::default()
+            }
+        );
+        assert_eq!(
+            KafkaPlugin::build_transaction_error(&Error::DuplicateInstruction(3)),
+            TransactionError {
+                kind: "DuplicateInstruction".to_owned(),
+                instruction_index: Some(3),
+                ..TransactionError::default()
+            }
+        );
+        assert_eq!(
+            KafkaPlugin::build_transaction_error(&Error::AccountInUse).kind,
+            "AccountInUse"
+        );
+    }
+}
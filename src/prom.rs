@@ -1,18 +1,31 @@
 use {
-    crate::version::VERSION as VERSION_INFO,
+    crate::{
+        retry::{RetryQueue, RetryRecord},
+        spill::SpillBuffer,
+        version::VERSION as VERSION_INFO,
+        Record,
+    },
     hyper::{
         server::conn::AddrStream,
         service::{make_service_fn, service_fn},
         Body, Request, Response, Server, StatusCode,
     },
     log::*,
-    prometheus::{GaugeVec, IntCounterVec, Opts, Registry, TextEncoder},
+    prometheus::{
+        Gauge, GaugeVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+    },
     rdkafka::{
         client::ClientContext,
+        message::Message,
         producer::{DeliveryResult, ProducerContext},
         statistics::Statistics,
     },
-    std::{io::Result as IoResult, net::SocketAddr, sync::Once, time::Duration},
+    std::{
+        io::Result as IoResult,
+        net::SocketAddr,
+        sync::{Arc, Once},
+        time::Duration,
+    },
     tokio::runtime::Runtime,
 };
 
@@ -39,6 +52,110 @@ lazy_static::lazy_static! {
         &["status"]
     ).unwrap();
 
+    pub static ref FILTERED_TRANSACTIONS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("filtered_transactions_total", "Transactions not published, by reason"),
+        &["reason"]
+    ).unwrap();
+
+    pub static ref UPLOAD_BLOCKS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_blocks_total", "Status of uploaded block metadata"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_ENTRIES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_entries_total", "Status of uploaded entries"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_STARTUP_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_startup_total", "Status of uploaded end of startup markers"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref UPLOAD_TOPIC_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("upload_topic_total", "Status of uploaded messages, by topic"),
+        &["topic", "status"]
+    ).unwrap();
+
+    pub static ref DELIVERIES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("deliveries_total", "Delivery reports from Kafka, by error code"),
+        &["topic", "status", "error"]
+    ).unwrap();
+
+    pub static ref RETRIES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("retries_total", "Records retried after failed deliveries"),
+        &["topic", "status"]
+    ).unwrap();
+
+    pub static ref RETRY_QUEUE_RECORDS: IntGauge = IntGauge::new(
+        "retry_queue_records", "Records waiting in the retry queue"
+    ).unwrap();
+
+    pub static ref QUEUE_FULL_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("queue_full_total", "Outcome of sends hitting a full producer queue"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref KAFKA_TRANSACTIONS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("kafka_transactions_total", "Status of per slot kafka transactions"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref COALESCED_ACCOUNTS_TOTAL: IntCounter = IntCounter::new(
+        "coalesced_accounts_total", "Account updates superseded within their slot"
+    ).unwrap();
+
+    pub static ref DELTA_UPDATES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("delta_updates_total", "Delta encoded account updates by kind"),
+        &["kind"]
+    ).unwrap();
+
+    pub static ref DELTA_CACHED_BYTES: IntGauge = IntGauge::new(
+        "delta_cached_bytes", "Account data kept to compute deltas"
+    ).unwrap();
+
+    pub static ref DATA_COMPRESSION_BYTES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "data_compression_bytes_total",
+            "Account data bytes before and after compression"
+        ),
+        &["stage"]
+    ).unwrap();
+
+    pub static ref ANCHOR_UNKNOWN_DISCRIMINATORS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "anchor_unknown_discriminators_total",
+            "Accounts and instructions of programs with an Anchor IDL not matching any discriminator"
+        ),
+        &["kind"]
+    ).unwrap();
+
+    pub static ref BUFFERED_EVENTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("buffered_events_total", "Events leaving the commitment buffer, by outcome"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref BUFFERED_SLOTS: IntGauge = IntGauge::new(
+        "buffered_slots", "Slots in the commitment buffer"
+    ).unwrap();
+
+    pub static ref BUFFERED_BYTES: IntGauge = IntGauge::new(
+        "buffered_bytes", "Size of events in the commitment buffer"
+    ).unwrap();
+
+    pub static ref SPILL_RECORDS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("spill_records_total", "Records spilled to and drained from disk"),
+        &["status"]
+    ).unwrap();
+
+    pub static ref SPILL_BYTES: IntGauge = IntGauge::new(
+        "spill_bytes", "Size of the spill buffer on disk"
+    ).unwrap();
+
+    pub static ref SPILL_DRAIN_LAG_SECONDS: Gauge = Gauge::new(
+        "spill_drain_lag_seconds", "Time the last drained record spent in the spill buffer"
+    ).unwrap();
+
     static ref KAFKA_STATS: GaugeVec = GaugeVec::new(
         Opts::new("kafka_stats", "librdkafka metrics"),
         &["broker", "metric"]
@@ -65,6 +182,27 @@ impl PrometheusService {
             register!(UPLOAD_ACCOUNTS_TOTAL);
             register!(UPLOAD_SLOTS_TOTAL);
             register!(UPLOAD_TRANSACTIONS_TOTAL);
+            register!(FILTERED_TRANSACTIONS_TOTAL);
+            register!(UPLOAD_BLOCKS_TOTAL);
+            register!(UPLOAD_ENTRIES_TOTAL);
+            register!(UPLOAD_STARTUP_TOTAL);
+            register!(UPLOAD_TOPIC_TOTAL);
+            register!(DELIVERIES_TOTAL);
+            register!(RETRIES_TOTAL);
+            register!(RETRY_QUEUE_RECORDS);
+            register!(QUEUE_FULL_TOTAL);
+            register!(KAFKA_TRANSACTIONS_TOTAL);
+            register!(COALESCED_ACCOUNTS_TOTAL);
+            register!(DELTA_UPDATES_TOTAL);
+            register!(DELTA_CACHED_BYTES);
+            register!(DATA_COMPRESSION_BYTES_TOTAL);
+            register!(ANCHOR_UNKNOWN_DISCRIMINATORS_TOTAL);
+            register!(BUFFERED_EVENTS_TOTAL);
+            register!(BUFFERED_SLOTS);
+            register!(BUFFERED_BYTES);
+            register!(SPILL_RECORDS_TOTAL);
+            register!(SPILL_BYTES);
+            register!(SPILL_DRAIN_LAG_SECONDS);
             register!(KAFKA_STATS);
 
             for (key, value) in &[
@@ -118,8 +256,19 @@ fn not_found_handler() -> Response<Body> {
         .unwrap()
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-pub struct StatsThreadedProducerContext;
+#[derive(Debug, Default, Clone)]
+pub struct StatsThreadedProducerContext {
+    retry_queue: Option<Arc<RetryQueue>>,
+    spill: Option<Arc<SpillBuffer>>,
+}
+
+impl StatsThreadedProducerContext {
+    /// Queue records with failed deliveries on the retry queue, if any, or else append them to
+    /// the spill buffer, if any.
+    pub fn new(retry_queue: Option<Arc<RetryQueue>>, spill: Option<Arc<SpillBuffer>>) -> Self {
+        Self { retry_queue, spill }
+    }
+}
 
 impl ClientContext for StatsThreadedProducerContext {
     fn stats(&self, statistics: Statistics) {
@@ -179,6 +328,46 @@ impl ClientContext for StatsThreadedProducerContext {
 }
 
 impl ProducerContext for StatsThreadedProducerContext {
-    type DeliveryOpaque = ();
-    fn delivery(&self, _: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {}
+    /// Number of previous failed delivery attempts.
+    type DeliveryOpaque = usize;
+
+    fn delivery(&self, result: &DeliveryResult<'_>, attempts: Self::DeliveryOpaque) {
+        let (error, message) = match result {
+            Ok(message) => {
+                DELIVERIES_TOTAL
+                    .with_label_values(&[message.topic(), "delivered", ""])
+                    .inc();
+                return;
+            }
+            Err((error, message)) => (error, message),
+        };
+
+        let code = error
+            .rdkafka_error_code()
+            .map(|code| format!("{code:?}"))
+            .unwrap_or_else(|| "unknown".to_owned());
+        DELIVERIES_TOTAL
+            .with_label_values(&[message.topic(), "failed", &code])
+            .inc();
+        if let Some(retry_queue) = &self.retry_queue {
+            retry_queue.push(RetryRecord {
+                topic: message.topic().to_owned(),
+                key: message.key().unwrap_or_default().to_vec(),
+                payload: message.payload().unwrap_or_default().to_vec(),
+                attempts: attempts + 1,
+            });
+        } else if let Some(spill) = &self.spill {
+            let record = Record {
+                topic: message.topic(),
+                key: message.key().unwrap_or_default(),
+                payload: message.payload().unwrap_or_default(),
+                slot: None,
+            };
+            match spill.append(record) {
+                Ok(true) => {}
+                Ok(false) => error!("Spill is full, dropping undelivered record"),
+                Err(error) => error!("Failed to spill undelivered record: {error}"),
+            }
+        }
+    }
 }
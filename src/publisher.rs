@@ -14,104 +14,259 @@
 
 use {
     crate::{
-        message_wrapper::EventMessage::{self, Account, Slot, Transaction},
+        message_wrapper::EventMessage::{
+            self, Account, Block, Entry, Slot, StartupComplete, Transaction,
+        },
         prom::{
-            StatsThreadedProducerContext, UPLOAD_ACCOUNTS_TOTAL, UPLOAD_SLOTS_TOTAL,
+            QUEUE_FULL_TOTAL, UPLOAD_ACCOUNTS_TOTAL, UPLOAD_BLOCKS_TOTAL, UPLOAD_ENTRIES_TOTAL,
+            UPLOAD_SLOTS_TOTAL, UPLOAD_STARTUP_TOTAL, UPLOAD_TOPIC_TOTAL,
             UPLOAD_TRANSACTIONS_TOTAL,
         },
-        Config, MessageWrapper, SlotStatusEvent, TransactionEvent, UpdateAccountEvent,
+        BlockMetadataEvent, Config, DataCompressor, DeltaEncoder, Encoding, EntryEvent,
+        MessageWrapper, QueueFullPolicy, Record, SchemaRegistry, Sink, SinkError, SlotStatus,
+        SlotStatusEvent, StartupCompleteEvent, TransactionEvent, UpdateAccountEvent,
     },
+    log::error,
     prost::Message,
-    rdkafka::{
-        error::KafkaError,
-        producer::{BaseRecord, Producer, ThreadedProducer},
+    serde::Serialize,
+    std::{
+        process, thread,
+        time::{Duration, Instant},
     },
-    std::time::Duration,
 };
 
+/// Pause between attempts to send to a full queue.
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Take down the validator.
+///
+/// Unwinding out of a plugin callback into the validator is undefined behaviour, so this aborts
+/// the process rather than panicking, except in tests.
+fn abort_validator(reason: &str) -> ! {
+    error!("{reason}, aborting the validator");
+    log::logger().flush();
+    if cfg!(test) {
+        panic!("{reason}");
+    }
+    process::abort()
+}
+
 pub struct Publisher {
-    producer: ThreadedProducer<StatsThreadedProducerContext>,
+    sink: Box<dyn Sink>,
     shutdown_timeout: Duration,
 
-    update_account_topic: String,
+    update_account_topics: Vec<String>,
     slot_status_topic: String,
-    transaction_topic: String,
+    transaction_topics: Vec<String>,
+    block_metadata_topic: String,
+    entry_topic: String,
+    startup_complete_topics: Vec<String>,
 
     wrap_messages: bool,
+    encoding: Encoding,
+    schema_registry: Option<SchemaRegistry>,
+    on_queue_full: QueueFullPolicy,
+    delta_encoder: Option<DeltaEncoder>,
+    data_compressor: Option<DataCompressor>,
 }
 
 impl Publisher {
-    pub fn new(producer: ThreadedProducer<StatsThreadedProducerContext>, config: &Config) -> Self {
+    pub fn new(
+        sink: Box<dyn Sink>,
+        schema_registry: Option<SchemaRegistry>,
+        config: &Config,
+    ) -> Self {
         Self {
-            producer,
+            sink,
             shutdown_timeout: Duration::from_millis(config.shutdown_timeout_ms),
-            update_account_topic: config.update_account_topic.clone(),
+            update_account_topics: config.update_account_topics(),
             slot_status_topic: config.slot_status_topic.clone(),
-            transaction_topic: config.transaction_topic.clone(),
+            transaction_topics: config.transaction_topics(),
+            block_metadata_topic: config.block_metadata_topic.clone(),
+            entry_topic: config.entry_topic.clone(),
+            startup_complete_topics: config.startup_complete_topics(),
             wrap_messages: config.wrap_messages,
+            encoding: config.encoding,
+            schema_registry,
+            on_queue_full: config.on_queue_full,
+            delta_encoder: config.delta_encoding.as_ref().map(DeltaEncoder::new),
+            data_compressor: config.data_compression.as_ref().map(DataCompressor::new),
         }
     }
 
-    pub fn update_account(&self, ev: UpdateAccountEvent) -> Result<(), KafkaError> {
+    pub fn update_account(&self, topic: &str, mut ev: UpdateAccountEvent) -> Result<(), SinkError> {
+        let slot = ev.slot;
+        let delta_encoded = self.delta_encoder.as_ref().map(|delta_encoder| {
+            delta_encoder.encode(topic, &mut ev);
+            (delta_encoder, ev.pubkey.clone())
+        });
+        if let Some(data_compressor) = &self.data_compressor {
+            data_compressor.compress(&mut ev);
+        }
         let temp_key;
         let (key, buf) = if self.wrap_messages {
             temp_key = self.copy_and_prepend(ev.pubkey.as_slice(), 65u8);
-            (&temp_key, Self::encode_with_wrapper(Account(Box::new(ev))))
+            (&temp_key, self.encode_with_wrapper(Account(Box::new(ev))))
         } else {
-            (&ev.pubkey, ev.encode_to_vec())
+            (&ev.pubkey, self.encode(&ev))
         };
-        let record = BaseRecord::<Vec<u8>, _>::to(&self.update_account_topic)
-            .key(key)
-            .payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
+        let result = self.send(topic, "UpdateAccountEvent", key, buf, Some(slot), false);
+        if let (Err(_), Some((delta_encoder, pubkey))) = (&result, delta_encoded) {
+            // The next delta would be against data consumers never received.
+            delta_encoder.forget(&pubkey);
+        }
         UPLOAD_ACCOUNTS_TOTAL
             .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
             .inc();
         result
     }
 
-    pub fn update_slot_status(&self, ev: SlotStatusEvent) -> Result<(), KafkaError> {
+    /// Publish a slot status update if a topic is configured, and pass it on to the sink.
+    pub fn update_slot_status(&self, ev: SlotStatusEvent) -> Result<(), SinkError> {
+        let slot = ev.slot;
+        let parent = (ev.parent != 0).then_some(ev.parent);
+        let status = SlotStatus::from_i32(ev.status).unwrap_or_default();
+        if self.wants_slot_status() {
+            self.publish_slot_status(ev)?;
+        }
+        self.sink.update_slot(slot, parent, status)
+    }
+
+    fn publish_slot_status(&self, ev: SlotStatusEvent) -> Result<(), SinkError> {
+        // A dead slot never reaches a commitment, so its status must not wait for one.
+        let slot = (ev.status != SlotStatus::Dead as i32).then_some(ev.slot);
+        let low_priority = ev.status == SlotStatus::Processed as i32;
         let temp_key;
         let (key, buf) = if self.wrap_messages {
             temp_key = self.copy_and_prepend(&ev.slot.to_le_bytes(), 83u8);
-            (&temp_key, Self::encode_with_wrapper(Slot(Box::new(ev))))
+            (&temp_key, self.encode_with_wrapper(Slot(Box::new(ev))))
         } else {
             temp_key = ev.slot.to_le_bytes().to_vec();
-            (&temp_key, ev.encode_to_vec())
+            (&temp_key, self.encode(&ev))
         };
-        let record = BaseRecord::<Vec<u8>, _>::to(&self.slot_status_topic)
-            .key(key)
-            .payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
+        let result = self.send(
+            &self.slot_status_topic,
+            "SlotStatusEvent",
+            key,
+            buf,
+            slot,
+            low_priority,
+        );
         UPLOAD_SLOTS_TOTAL
             .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
             .inc();
         result
     }
 
-    pub fn update_transaction(&self, ev: TransactionEvent) -> Result<(), KafkaError> {
+    pub fn update_transaction(&self, topic: &str, ev: TransactionEvent) -> Result<(), SinkError> {
+        let slot = ev.slot;
+        let low_priority = ev.is_vote;
         let temp_key;
         let (key, buf) = if self.wrap_messages {
             temp_key = self.copy_and_prepend(ev.signature.as_slice(), 84u8);
             (
                 &temp_key,
-                Self::encode_with_wrapper(Transaction(Box::new(ev))),
+                self.encode_with_wrapper(Transaction(Box::new(ev))),
             )
         } else {
-            (&ev.signature, ev.encode_to_vec())
+            (&ev.signature, self.encode(&ev))
         };
-        let record = BaseRecord::<Vec<u8>, _>::to(&self.transaction_topic)
-            .key(key)
-            .payload(&buf);
-        let result = self.producer.send(record).map(|_| ()).map_err(|(e, _)| e);
+        let result = self.send(
+            topic,
+            "TransactionEvent",
+            key,
+            buf,
+            Some(slot),
+            low_priority,
+        );
         UPLOAD_TRANSACTIONS_TOTAL
             .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
             .inc();
         result
     }
 
+    pub fn update_block_metadata(&self, ev: BlockMetadataEvent) -> Result<(), SinkError> {
+        let slot = ev.slot;
+        let temp_key;
+        let (key, buf) = if self.wrap_messages {
+            temp_key = self.copy_and_prepend(&ev.slot.to_le_bytes(), 66u8);
+            (&temp_key, self.encode_with_wrapper(Block(Box::new(ev))))
+        } else {
+            temp_key = ev.slot.to_le_bytes().to_vec();
+            (&temp_key, self.encode(&ev))
+        };
+        let result = self.send(
+            &self.block_metadata_topic,
+            "BlockMetadataEvent",
+            key,
+            buf,
+            Some(slot),
+            false,
+        );
+        UPLOAD_BLOCKS_TOTAL
+            .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
+            .inc();
+        result
+    }
+
+    pub fn update_entry(&self, ev: EntryEvent) -> Result<(), SinkError> {
+        let slot = ev.slot;
+        let mut temp_key = Vec::with_capacity(17);
+        if self.wrap_messages {
+            temp_key.push(69u8);
+        }
+        temp_key.extend_from_slice(&ev.slot.to_le_bytes());
+        temp_key.extend_from_slice(&ev.index.to_le_bytes());
+        let buf = if self.wrap_messages {
+            self.encode_with_wrapper(Entry(Box::new(ev)))
+        } else {
+            self.encode(&ev)
+        };
+        let result = self.send(
+            &self.entry_topic,
+            "EntryEvent",
+            &temp_key,
+            buf,
+            Some(slot),
+            false,
+        );
+        UPLOAD_ENTRIES_TOTAL
+            .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
+            .inc();
+        result
+    }
+
+    /// Publish the end of startup marker, see [`Config::startup_complete_topics`].
+    pub fn notify_end_of_startup(&self, ev: StartupCompleteEvent) -> Result<(), SinkError> {
+        const KEY: &[u8] = b"startup_complete";
+        let (key, buf) = if self.wrap_messages {
+            (
+                self.copy_and_prepend(KEY, 67u8),
+                self.encode_with_wrapper(StartupComplete(Box::new(ev))),
+            )
+        } else {
+            (KEY.to_vec(), self.encode(&ev))
+        };
+
+        for topic in &self.startup_complete_topics {
+            let result = self.send(
+                topic,
+                "StartupCompleteEvent",
+                &key,
+                buf.clone(),
+                None,
+                false,
+            );
+            UPLOAD_STARTUP_TOTAL
+                .with_label_values(&[if result.is_ok() { "success" } else { "failed" }])
+                .inc();
+            result?;
+        }
+        Ok(())
+    }
+
     pub fn wants_update_account(&self) -> bool {
-        !self.update_account_topic.is_empty()
+        !self.update_account_topics.is_empty()
     }
 
     pub fn wants_slot_status(&self) -> bool {
@@ -119,14 +274,98 @@ impl Publisher {
     }
 
     pub fn wants_transaction(&self) -> bool {
-        !self.transaction_topic.is_empty()
+        !self.transaction_topics.is_empty()
     }
 
-    fn encode_with_wrapper(message: EventMessage) -> Vec<u8> {
-        MessageWrapper {
-            event_message: Some(message),
+    pub fn wants_block_metadata(&self) -> bool {
+        !self.block_metadata_topic.is_empty()
+    }
+
+    pub fn wants_entry(&self) -> bool {
+        !self.entry_topic.is_empty()
+    }
+
+    /// Send a payload of the named message type and count it by topic.
+    fn send(
+        &self,
+        topic: &str,
+        message: &str,
+        key: &[u8],
+        payload: Vec<u8>,
+        slot: Option<u64>,
+        low_priority: bool,
+    ) -> Result<(), SinkError> {
+        let result = self.send_record(topic, message, key, payload, slot, low_priority);
+        UPLOAD_TOPIC_TOTAL
+            .with_label_values(&[topic, if result.is_ok() { "success" } else { "failed" }])
+            .inc();
+        result
+    }
+
+    /// Send a payload of the named message type, framed for the schema registry if configured.
+    fn send_record(
+        &self,
+        topic: &str,
+        message: &str,
+        key: &[u8],
+        payload: Vec<u8>,
+        slot: Option<u64>,
+        low_priority: bool,
+    ) -> Result<(), SinkError> {
+        let payload = match &self.schema_registry {
+            Some(registry) if self.wrap_messages => {
+                registry.frame(topic, "MessageWrapper", payload)
+            }
+            Some(registry) => registry.frame(topic, message, payload),
+            None => payload,
+        };
+        let record = Record {
+            topic,
+            key,
+            payload: &payload,
+            slot,
+        };
+
+        let mut result = self.sink.send(record);
+        if !matches!(&result, Err(error) if error.is_queue_full()) {
+            return result;
+        }
+        let timeout_ms = match self.on_queue_full {
+            QueueFullPolicy::Drop => None,
+            QueueFullPolicy::Block { timeout_ms } => Some(timeout_ms),
+            QueueFullPolicy::DropLowestPriority { timeout_ms } => {
+                (!low_priority).then_some(timeout_ms)
+            }
+            QueueFullPolicy::Fail => abort_validator("Kafka producer queue is full"),
+        };
+        if let Some(timeout_ms) = timeout_ms {
+            let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+            while matches!(&result, Err(error) if error.is_queue_full()) {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                thread::sleep(remaining.min(QUEUE_FULL_BACKOFF));
+                result = self.sink.send(record);
+            }
+        }
+        QUEUE_FULL_TOTAL
+            .with_label_values(&[if result.is_ok() { "sent" } else { "dropped" }])
+            .inc();
+        result
+    }
+
+    fn encode<M: Message + Serialize>(&self, message: &M) -> Vec<u8> {
+        match self.encoding {
+            Encoding::Protobuf => message.encode_to_vec(),
+            Encoding::Json => serde_json::to_vec(message).expect("events serialize to JSON"),
         }
-        .encode_to_vec()
+    }
+
+    fn encode_with_wrapper(&self, message: EventMessage) -> Vec<u8> {
+        self.encode(&MessageWrapper {
+            event_message: Some(message),
+        })
     }
 
     fn copy_and_prepend(&self, data: &[u8], prefix: u8) -> Vec<u8> {
@@ -139,6 +378,169 @@ impl Publisher {
 
 impl Drop for Publisher {
     fn drop(&mut self) {
-        let _ = self.producer.flush(self.shutdown_timeout);
+        let _ = self.sink.flush(self.shutdown_timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::Publisher,
+        crate::{
+            message_wrapper::EventMessage, Config, MessageWrapper, QueueFullPolicy, Record, Sink,
+            SinkError, TransactionEvent, UpdateAccountEvent,
+        },
+        prost::Message,
+        rdkafka::error::{KafkaError, RDKafkaErrorCode},
+        std::{
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc, Mutex,
+            },
+            time::Duration,
+        },
+    };
+
+    type Sent = Arc<Mutex<Vec<(String, Vec<u8>, Vec<u8>)>>>;
+
+    /// Stub of a producer whose queue is full for the first `queue_full` sends.
+    struct MemorySink {
+        sent: Sent,
+        queue_full: AtomicUsize,
+    }
+
+    impl MemorySink {
+        fn publisher(config: &Config, queue_full: usize) -> (Publisher, Sent) {
+            let sent = Sent::default();
+            let sink = Self {
+                sent: sent.clone(),
+                queue_full: AtomicUsize::new(queue_full),
+            };
+            (Publisher::new(Box::new(sink), None, config), sent)
+        }
+    }
+
+    impl Sink for MemorySink {
+        fn send(&self, record: Record) -> Result<(), SinkError> {
+            let full = self
+                .queue_full
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if full {
+                return Err(SinkError::Kafka(KafkaError::MessageProduction(
+                    RDKafkaErrorCode::QueueFull,
+                )));
+            }
+            self.sent.lock().unwrap().push((
+                record.topic.to_owned(),
+                record.key.to_vec(),
+                record.payload.to_vec(),
+            ));
+            Ok(())
+        }
+
+        fn flush(&self, _timeout: Duration) -> Result<(), SinkError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_publish_wrapped_account() {
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            wrap_messages: true,
+            ..Config::default()
+        };
+        let (publisher, sent) = MemorySink::publisher(&config, 0);
+        assert!(publisher.wants_update_account());
+        assert!(!publisher.wants_transaction());
+
+        let event = UpdateAccountEvent {
+            slot: 42,
+            pubkey: vec![1; 32],
+            ..UpdateAccountEvent::default()
+        };
+        publisher.update_account("accounts", event.clone()).unwrap();
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        let (topic, key, payload) = &sent[0];
+        assert_eq!(topic, "accounts");
+        assert_eq!(key[0], 65);
+        assert_eq!(&key[1..], event.pubkey.as_slice());
+        let wrapper = MessageWrapper::decode(payload.as_slice()).unwrap();
+        assert_eq!(
+            wrapper.event_message,
+            Some(EventMessage::Account(Box::new(event)))
+        );
+    }
+
+    fn transaction(is_vote: bool) -> TransactionEvent {
+        TransactionEvent {
+            is_vote,
+            signature: vec![2; 64],
+            slot: 42,
+            ..TransactionEvent::default()
+        }
+    }
+
+    fn config(on_queue_full: QueueFullPolicy) -> Config {
+        Config {
+            transaction_topic: "transactions".to_owned(),
+            on_queue_full,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_queue_full_drop() {
+        let (publisher, sent) = MemorySink::publisher(&config(QueueFullPolicy::Drop), 1);
+        let error = publisher
+            .update_transaction("transactions", transaction(false))
+            .unwrap_err();
+        assert!(error.is_queue_full());
+        publisher
+            .update_transaction("transactions", transaction(false))
+            .unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_queue_full_block() {
+        let policy = QueueFullPolicy::Block { timeout_ms: 10_000 };
+        let (publisher, sent) = MemorySink::publisher(&config(policy), 3);
+        publisher
+            .update_transaction("transactions", transaction(true))
+            .unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        let policy = QueueFullPolicy::Block { timeout_ms: 0 };
+        let (publisher, sent) = MemorySink::publisher(&config(policy), 3);
+        assert!(publisher
+            .update_transaction("transactions", transaction(false))
+            .is_err());
+        assert!(sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_queue_full_drop_lowest_priority() {
+        let policy = QueueFullPolicy::DropLowestPriority { timeout_ms: 10_000 };
+        let (publisher, sent) = MemorySink::publisher(&config(policy), 3);
+        assert!(publisher
+            .update_transaction("transactions", transaction(true))
+            .is_err());
+        assert!(sent.lock().unwrap().is_empty());
+
+        publisher
+            .update_transaction("transactions", transaction(false))
+            .unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "queue is full")]
+    fn test_queue_full_fail() {
+        let (publisher, _sent) = MemorySink::publisher(&config(QueueFullPolicy::Fail), 1);
+        let _ = publisher.update_transaction("transactions", transaction(false));
     }
 }
@@ -0,0 +1,177 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        prom::{RETRIES_TOTAL, RETRY_QUEUE_RECORDS},
+        RetryConfig, RetryQueueFull,
+    },
+    std::{collections::VecDeque, sync::Mutex},
+};
+
+/// A record whose delivery failed, owned so it outlives the delivery report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryRecord {
+    pub topic: String,
+    pub key: Vec<u8>,
+    pub payload: Vec<u8>,
+    /// Number of failed delivery attempts so far.
+    pub attempts: usize,
+}
+
+/// Bounded queue of records to send again after failed deliveries.
+#[derive(Debug)]
+pub struct RetryQueue {
+    records: Mutex<VecDeque<RetryRecord>>,
+    max_records: usize,
+    max_attempts: usize,
+    when_full: RetryQueueFull,
+}
+
+impl RetryQueue {
+    pub fn new(config: &RetryConfig) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::new()),
+            max_records: config.max_records,
+            max_attempts: config.max_attempts,
+            when_full: config.when_full,
+        }
+    }
+
+    /// Queue a failed record, unless it used up its attempts or the queue is full.
+    pub fn push(&self, record: RetryRecord) {
+        if record.attempts >= self.max_attempts {
+            RETRIES_TOTAL
+                .with_label_values(&[&record.topic, "exhausted"])
+                .inc();
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() >= self.max_records {
+            let dropped = match self.when_full {
+                RetryQueueFull::DropNewest => Some(record),
+                RetryQueueFull::DropOldest => {
+                    let oldest = records.pop_front();
+                    records.push_back(record);
+                    oldest
+                }
+            };
+            if let Some(dropped) = dropped {
+                RETRIES_TOTAL
+                    .with_label_values(&[&dropped.topic, "dropped"])
+                    .inc();
+            }
+        } else {
+            RETRIES_TOTAL
+                .with_label_values(&[&record.topic, "queued"])
+                .inc();
+            records.push_back(record);
+        }
+        RETRY_QUEUE_RECORDS.set(records.len() as i64);
+    }
+
+    /// Take the oldest record for sending.
+    pub fn pop(&self) -> Option<RetryRecord> {
+        let mut records = self.records.lock().unwrap();
+        let record = records.pop_front();
+        RETRY_QUEUE_RECORDS.set(records.len() as i64);
+        record
+    }
+
+    /// Put back a record that could not be sent, keeping its place in line.
+    pub fn unpop(&self, record: RetryRecord) {
+        let mut records = self.records.lock().unwrap();
+        records.push_front(record);
+        RETRY_QUEUE_RECORDS.set(records.len() as i64);
+    }
+
+    /// Drop all waiting records, counting them as dropped, and return their number.
+    pub fn discard(&self) -> usize {
+        let mut records = self.records.lock().unwrap();
+        for record in records.iter() {
+            RETRIES_TOTAL
+                .with_label_values(&[&record.topic, "dropped"])
+                .inc();
+        }
+        let dropped = records.len();
+        records.clear();
+        RETRY_QUEUE_RECORDS.set(0);
+        dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{RetryQueue, RetryRecord},
+        crate::{RetryConfig, RetryQueueFull},
+    };
+
+    fn record(key: u8, attempts: usize) -> RetryRecord {
+        RetryRecord {
+            topic: "accounts".to_owned(),
+            key: vec![key],
+            payload: vec![],
+            attempts,
+        }
+    }
+
+    fn keys(queue: &RetryQueue) -> Vec<u8> {
+        std::iter::from_fn(|| queue.pop())
+            .map(|record| record.key[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_retry_queue() {
+        let config = RetryConfig {
+            max_records: 2,
+            max_attempts: 3,
+            when_full: RetryQueueFull::DropNewest,
+        };
+        let queue = RetryQueue::new(&config);
+        queue.push(record(1, 1));
+        queue.push(record(2, 3));
+        queue.push(record(3, 2));
+        queue.push(record(4, 1));
+        assert_eq!(queue.len(), 2);
+
+        let first = queue.pop().unwrap();
+        queue.unpop(first);
+        assert_eq!(keys(&queue), vec![1, 3]);
+        assert!(queue.is_empty());
+
+        let queue = RetryQueue::new(&RetryConfig {
+            when_full: RetryQueueFull::DropOldest,
+            ..config
+        });
+        for key in 1..=4 {
+            queue.push(record(key, 1));
+        }
+        assert_eq!(keys(&queue), vec![3, 4]);
+
+        queue.push(record(5, 1));
+        assert_eq!(queue.discard(), 1);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::SchemaRegistryConfig,
+    log::info,
+    prost::Message,
+    prost_types::FileDescriptorSet,
+    serde::Deserialize,
+    std::{collections::HashMap, time::Duration},
+};
+
+/// The schema registered for every topic.
+pub const EVENT_PROTO: &str = include_str!("../proto/event.proto");
+
+/// Descriptors of `proto/event.proto`, emitted by the build script.
+const EVENT_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/event_descriptor_set.bin"));
+
+/// Frames protobuf payloads in the Confluent Schema Registry wire format.
+///
+/// Each payload is prefixed with a zero magic byte, the big-endian schema id of the topic and
+/// the index of the message type within `proto/event.proto`.
+#[derive(Debug)]
+pub struct SchemaRegistry {
+    schema_ids: HashMap<String, u32>,
+    message_indexes: HashMap<String, usize>,
+}
+
+#[derive(Deserialize)]
+struct RegisterResponse {
+    id: u32,
+}
+
+impl SchemaRegistry {
+    /// Take schema ids from config, registering `proto/event.proto` for all other topics.
+    pub fn new(config: &SchemaRegistryConfig, topics: &[String]) -> Result<Self, String> {
+        let mut schema_ids = config.schema_ids.clone();
+        for topic in topics {
+            if schema_ids.contains_key(topic) {
+                continue;
+            }
+            let url = config
+                .url
+                .as_ref()
+                .ok_or_else(|| format!("no schema id or registry url for topic {topic}"))?;
+            let id = Self::register(url, topic, Duration::from_millis(config.timeout_ms))?;
+            info!("Registered schema {id} for topic {topic}");
+            schema_ids.insert(topic.clone(), id);
+        }
+
+        Ok(Self {
+            schema_ids,
+            message_indexes: Self::message_indexes(EVENT_DESCRIPTOR_SET)?,
+        })
+    }
+
+    /// Register `proto/event.proto` under the `<topic>-value` subject and return its id.
+    pub fn register(url: &str, topic: &str, timeout: Duration) -> Result<u32, String> {
+        let response = reqwest::blocking::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|error| format!("failed to create schema registry client: {error}"))?
+            .post(format!(
+                "{}/subjects/{}-value/versions",
+                url.trim_end_matches('/'),
+                topic
+            ))
+            .header("Content-Type", "application/vnd.schemaregistry.v1+json")
+            .json(&serde_json::json!({
+                "schemaType": "PROTOBUF",
+                "schema": EVENT_PROTO,
+            }))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| format!("failed to register schema for topic {topic}: {error}"))?;
+        response
+            .json::<RegisterResponse>()
+            .map(|response| response.id)
+            .map_err(|error| format!("invalid schema registry response for {topic}: {error}"))
+    }
+
+    /// Prefix a payload of the named message type for the topic.
+    ///
+    /// Payloads for topics without schema id are returned unchanged.
+    pub fn frame(&self, topic: &str, message: &str, payload: Vec<u8>) -> Vec<u8> {
+        let (Some(schema_id), Some(index)) =
+            (self.schema_ids.get(topic), self.message_indexes.get(message))
+        else {
+            return payload;
+        };
+
+        let mut buf = Vec::with_capacity(payload.len() + 8);
+        buf.push(0u8);
+        buf.extend_from_slice(&schema_id.to_be_bytes());
+        if *index == 0 {
+            // Shortcut for the first message type.
+            buf.push(0u8);
+        } else {
+            // Array length and indexes as zig-zag varints.
+            prost::encoding::encode_varint(2, &mut buf);
+            prost::encoding::encode_varint(*index as u64 * 2, &mut buf);
+        }
+        buf.extend_from_slice(&payload);
+        buf
+    }
+
+    /// Positions of the top-level messages of `proto/event.proto` in an encoded descriptor set.
+    fn message_indexes(descriptor_set: &[u8]) -> Result<HashMap<String, usize>, String> {
+        let descriptor_set = FileDescriptorSet::decode(descriptor_set)
+            .map_err(|error| format!("invalid event descriptor set: {error}"))?;
+        let file = descriptor_set
+            .file
+            .into_iter()
+            .find(|file| file.name() == "event.proto")
+            .ok_or("event.proto missing from descriptor set")?;
+        Ok(file
+            .message_type
+            .into_iter()
+            .enumerate()
+            .map(|(index, message)| (message.name().to_owned(), index))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{SchemaRegistry, EVENT_DESCRIPTOR_SET, EVENT_PROTO},
+        crate::SchemaRegistryConfig,
+        hyper::{
+            service::{make_service_fn, service_fn},
+            Body, Response, Server,
+        },
+        std::{
+            collections::HashMap,
+            convert::Infallible,
+            sync::{Arc, Mutex},
+        },
+        tokio::runtime::Runtime,
+    };
+
+    #[test]
+    fn test_message_indexes() {
+        let indexes = SchemaRegistry::message_indexes(EVENT_DESCRIPTOR_SET).unwrap();
+        assert_eq!(indexes["UpdateAccountEvent"], 0);
+        assert_eq!(indexes["SlotStatusEvent"], 1);
+        assert!(indexes.contains_key("MessageWrapper"));
+        assert!(!indexes.contains_key("SlotStatus"));
+        assert!(!indexes.contains_key("ProgramAccountCount"));
+    }
+
+    #[test]
+    fn test_frame() {
+        let config = SchemaRegistryConfig {
+            schema_ids: HashMap::from([("accounts".to_owned(), 258), ("slots".to_owned(), 1)]),
+            url: None,
+            ..SchemaRegistryConfig::default()
+        };
+        let registry =
+            SchemaRegistry::new(&config, &["accounts".to_owned(), "slots".to_owned()]).unwrap();
+
+        assert_eq!(
+            registry.frame("accounts", "UpdateAccountEvent", vec![42]),
+            vec![0, 0, 0, 1, 2, 0, 42]
+        );
+        assert_eq!(
+            registry.frame("slots", "SlotStatusEvent", vec![42]),
+            vec![0, 0, 0, 0, 1, 2, 2, 42]
+        );
+        assert_eq!(
+            registry.frame("other", "SlotStatusEvent", vec![42]),
+            vec![42]
+        );
+
+        assert!(SchemaRegistry::new(&config, &["other".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn test_register() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let runtime = Runtime::new().unwrap();
+        let address = {
+            let requests = requests.clone();
+            runtime.block_on(async move {
+                let make_service = make_service_fn(move |_| {
+                    let requests = requests.clone();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| {
+                            let requests = requests.clone();
+                            async move {
+                                let path = req.uri().path().to_owned();
+                                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                                requests.lock().unwrap().push((path, body));
+                                Ok::<_, Infallible>(Response::new(Body::from(r#"{"id":7}"#)))
+                            }
+                        }))
+                    }
+                });
+                let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+                let address = server.local_addr();
+                tokio::spawn(server);
+                address
+            })
+        };
+
+        let config = SchemaRegistryConfig {
+            schema_ids: HashMap::from([("slots".to_owned(), 1)]),
+            url: Some(format!("http://{address}/")),
+            ..SchemaRegistryConfig::default()
+        };
+        let registry =
+            SchemaRegistry::new(&config, &["accounts".to_owned(), "slots".to_owned()]).unwrap();
+        assert_eq!(registry.schema_ids["accounts"], 7);
+        assert_eq!(registry.schema_ids["slots"], 1);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, "/subjects/accounts-value/versions");
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].1).unwrap();
+        assert_eq!(body["schemaType"], "PROTOBUF");
+        assert_eq!(body["schema"], EVENT_PROTO);
+    }
+}
@@ -0,0 +1,535 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        prom::{StatsThreadedProducerContext, RETRIES_TOTAL},
+        retry::RetryQueue,
+        spill::SpillBuffer,
+        Encoding, SlotStatus,
+    },
+    base64::{engine::general_purpose::STANDARD as BASE64, Engine},
+    log::{error, warn},
+    rdkafka::{
+        error::{KafkaError, RDKafkaErrorCode},
+        producer::{BaseRecord, Producer, ThreadedProducer},
+    },
+    std::{
+        collections::HashMap,
+        fmt::{Display, Formatter},
+        fs::{self, File, OpenOptions},
+        io::{self, BufWriter, Write},
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+/// How often the retry thread sends failed records again while no new records arrive.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A single encoded event on its way to a sink.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub topic: &'a str,
+    pub key: &'a [u8],
+    pub payload: &'a [u8],
+    /// Slot of the event, if it belongs to one.
+    pub slot: Option<u64>,
+}
+
+/// Output backend of the [`Publisher`](crate::Publisher).
+pub trait Sink: Send + Sync {
+    /// Queue a record for publishing.
+    fn send(&self, record: Record) -> Result<(), SinkError>;
+
+    /// Track the status of a slot, for sinks publishing per slot.
+    fn update_slot(
+        &self,
+        _slot: u64,
+        _parent: Option<u64>,
+        _status: SlotStatus,
+    ) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Wait for all queued records to be published.
+    fn flush(&self, timeout: Duration) -> Result<(), SinkError>;
+}
+
+#[derive(Debug)]
+pub enum SinkError {
+    Kafka(KafkaError),
+    Io(io::Error),
+}
+
+impl SinkError {
+    /// The producer queue has no room for more records.
+    pub fn is_queue_full(&self) -> bool {
+        matches!(
+            self,
+            Self::Kafka(KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull))
+        )
+    }
+}
+
+impl Display for SinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Kafka(error) => write!(f, "kafka error: {error}"),
+            Self::Io(error) => write!(f, "io error: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Kafka(error) => Some(error),
+            Self::Io(error) => Some(error),
+        }
+    }
+}
+
+impl From<KafkaError> for SinkError {
+    fn from(error: KafkaError) -> Self {
+        Self::Kafka(error)
+    }
+}
+
+impl From<io::Error> for SinkError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Publishes records to Kafka.
+pub struct KafkaSink {
+    producer: Arc<ThreadedProducer<StatsThreadedProducerContext>>,
+    retry_queue: Option<Arc<RetryQueue>>,
+    spill: Option<Arc<SpillBuffer>>,
+    /// Set to stop the retry thread.
+    stop_retries: Arc<AtomicBool>,
+    retry_thread: Option<JoinHandle<()>>,
+}
+
+impl KafkaSink {
+    /// Records on the retry queue are sent again ahead of new records, and by a background
+    /// thread while no new records arrive.
+    /// Records the producer queue has no room for go to the spill buffer, if any.
+    pub fn new(
+        producer: ThreadedProducer<StatsThreadedProducerContext>,
+        retry_queue: Option<Arc<RetryQueue>>,
+        spill: Option<Arc<SpillBuffer>>,
+    ) -> io::Result<Self> {
+        let producer = Arc::new(producer);
+        let stop_retries = Arc::new(AtomicBool::new(false));
+        let retry_thread = retry_queue
+            .clone()
+            .map(|retry_queue| {
+                let producer = producer.clone();
+                let stop_retries = stop_retries.clone();
+                thread::Builder::new()
+                    .name("kafkaRetry".to_owned())
+                    .spawn(move || {
+                        while !stop_retries.load(Ordering::Relaxed) {
+                            thread::sleep(RETRY_INTERVAL);
+                            send_retries(&producer, &retry_queue);
+                        }
+                    })
+            })
+            .transpose()?;
+        Ok(Self {
+            producer,
+            retry_queue,
+            spill,
+            stop_retries,
+            retry_thread,
+        })
+    }
+
+    fn produce(&self, record: Record, attempts: usize) -> Result<(), KafkaError> {
+        produce(&self.producer, record, attempts)
+    }
+
+    fn send_retries(&self) {
+        if let Some(retry_queue) = &self.retry_queue {
+            send_retries(&self.producer, retry_queue);
+        }
+    }
+
+    /// Move records from the spill buffer to the producer until its queue is full.
+    fn drain_spill(&self, spill: &SpillBuffer) -> Result<(), SinkError> {
+        spill.drain(|record| match self.produce(record, 0) {
+            Ok(()) => true,
+            Err(KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull)) => false,
+            Err(error) => {
+                error!("Dropping spilled record for {}: {error}", record.topic);
+                true
+            }
+        })?;
+        Ok(())
+    }
+}
+
+impl Sink for KafkaSink {
+    fn send(&self, record: Record) -> Result<(), SinkError> {
+        self.send_retries();
+        let Some(spill) = &self.spill else {
+            return self.produce(record, 0).map_err(SinkError::Kafka);
+        };
+
+        // Records must not overtake spilled ones, so they join the spill until it is drained.
+        self.drain_spill(spill)?;
+        if spill.is_empty() {
+            match self.produce(record, 0) {
+                Err(KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull)) => {}
+                result => return result.map_err(SinkError::Kafka),
+            }
+        }
+        if spill.append(record)? {
+            Ok(())
+        } else {
+            Err(SinkError::Kafka(KafkaError::MessageProduction(
+                RDKafkaErrorCode::QueueFull,
+            )))
+        }
+    }
+
+    fn flush(&self, timeout: Duration) -> Result<(), SinkError> {
+        let deadline = Instant::now() + timeout;
+        if let Some(spill) = &self.spill {
+            self.drain_spill(spill)?;
+            spill.flush()?;
+        }
+        // Deliveries failing during the flush queue their records for another attempt.
+        loop {
+            self.send_retries();
+            self.producer
+                .flush(deadline.saturating_duration_since(Instant::now()))
+                .map_err(SinkError::Kafka)?;
+            let retries_left = self
+                .retry_queue
+                .as_ref()
+                .map_or(false, |retry_queue| !retry_queue.is_empty());
+            if !retries_left || Instant::now() >= deadline {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for KafkaSink {
+    fn drop(&mut self) {
+        self.stop_retries.store(true, Ordering::Relaxed);
+        if let Some(retry_thread) = self.retry_thread.take() {
+            let _ = retry_thread.join();
+        }
+        if let Some(retry_queue) = &self.retry_queue {
+            let dropped = retry_queue.discard();
+            if dropped > 0 {
+                warn!("Dropping {dropped} records waiting for another delivery attempt");
+            }
+        }
+    }
+}
+
+fn produce(
+    producer: &ThreadedProducer<StatsThreadedProducerContext>,
+    record: Record,
+    attempts: usize,
+) -> Result<(), KafkaError> {
+    let kafka_record = BaseRecord::<[u8], [u8], usize>::with_opaque_to(record.topic, attempts)
+        .key(record.key)
+        .payload(record.payload);
+    producer.send(kafka_record).map_err(|(e, _)| e)
+}
+
+/// Move records from the retry queue to the producer until either runs out.
+fn send_retries(
+    producer: &ThreadedProducer<StatsThreadedProducerContext>,
+    retry_queue: &RetryQueue,
+) {
+    while let Some(record) = retry_queue.pop() {
+        let sent = produce(
+            producer,
+            Record {
+                topic: &record.topic,
+                key: &record.key,
+                payload: &record.payload,
+                slot: None,
+            },
+            record.attempts,
+        );
+        if sent.is_err() {
+            retry_queue.unpop(record);
+            return;
+        }
+        RETRIES_TOTAL
+            .with_label_values(&[&record.topic, "sent"])
+            .inc();
+    }
+}
+
+/// Writes length-delimited payloads into one file per topic, rotated by size or slot.
+pub struct FileSink {
+    directory: PathBuf,
+    max_file_size: u64,
+    slots_per_file: u64,
+    files: Mutex<HashMap<String, TopicFile>>,
+}
+
+struct TopicFile {
+    writer: BufWriter<File>,
+    size: u64,
+    slot_bucket: Option<u64>,
+    sequence: u64,
+}
+
+impl FileSink {
+    /// A zero `max_file_size` or `slots_per_file` disables the respective rotation.
+    pub fn new(directory: PathBuf, max_file_size: u64, slots_per_file: u64) -> io::Result<Self> {
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            max_file_size,
+            slots_per_file,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Create the file for records from `slot` on, with the first free sequence number from
+    /// `sequence` on.
+    ///
+    /// Existing files are never appended to, as a file cut short by a restart may end in a
+    /// partial record.
+    fn open(&self, topic: &str, slot: Option<u64>, mut sequence: u64) -> io::Result<TopicFile> {
+        let file = loop {
+            let path = self.directory.join(format!(
+                "{}-{}-{}.pb",
+                topic,
+                slot.unwrap_or_default(),
+                sequence
+            ));
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => sequence += 1,
+                result => break result?,
+            }
+        };
+        Ok(TopicFile {
+            size: 0,
+            writer: BufWriter::new(file),
+            slot_bucket: self.slot_bucket(slot),
+            sequence,
+        })
+    }
+
+    fn slot_bucket(&self, slot: Option<u64>) -> Option<u64> {
+        match (slot, self.slots_per_file) {
+            (Some(slot), slots_per_file) if slots_per_file > 0 => Some(slot / slots_per_file),
+            _ => None,
+        }
+    }
+
+    /// Records of slots before the bucket of the file, e.g. of a lower commitment level, are
+    /// written to the file rather than rotating back to their bucket.
+    fn needs_rotation(&self, file: &TopicFile, slot: Option<u64>, len: u64) -> bool {
+        let full = self.max_file_size > 0 && file.size > 0 && file.size + len > self.max_file_size;
+        full || self.slot_bucket(slot) > file.slot_bucket
+    }
+}
+
+impl Sink for FileSink {
+    fn send(&self, record: Record) -> Result<(), SinkError> {
+        let mut buf = Vec::with_capacity(record.payload.len() + 10);
+        prost::encoding::encode_varint(record.payload.len() as u64, &mut buf);
+        buf.extend_from_slice(record.payload);
+
+        let mut files = self.files.lock().unwrap();
+        if !files.contains_key(record.topic) {
+            let file = self.open(record.topic, record.slot, 0)?;
+            files.insert(record.topic.to_owned(), file);
+        }
+        let file = files.get_mut(record.topic).expect("file is open");
+        if self.needs_rotation(file, record.slot, buf.len() as u64) {
+            file.writer.flush()?;
+            *file = self.open(record.topic, record.slot, file.sequence + 1)?;
+        }
+        file.writer.write_all(&buf)?;
+        file.size += buf.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&self, _timeout: Duration) -> Result<(), SinkError> {
+        for file in self.files.lock().unwrap().values_mut() {
+            file.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints records as JSON lines to stdout, with base58 keys.
+///
+/// Payloads are embedded as is with the JSON encoding and base64 encoded otherwise.
+#[derive(Debug, Default)]
+pub struct StdoutSink {
+    encoding: Encoding,
+}
+
+impl StdoutSink {
+    pub fn new(encoding: Encoding) -> Self {
+        Self { encoding }
+    }
+}
+
+impl Sink for StdoutSink {
+    fn send(&self, record: Record) -> Result<(), SinkError> {
+        let payload = match self.encoding {
+            Encoding::Json => serde_json::from_slice(record.payload)
+                .unwrap_or_else(|_| serde_json::Value::from(BASE64.encode(record.payload))),
+            Encoding::Protobuf => serde_json::Value::from(BASE64.encode(record.payload)),
+        };
+        let line = serde_json::json!({
+            "topic": record.topic,
+            "key": bs58::encode(record.key).into_string(),
+            "slot": record.slot,
+            "payload": payload,
+        });
+        writeln!(io::stdout().lock(), "{line}")?;
+        Ok(())
+    }
+
+    fn flush(&self, _timeout: Duration) -> Result<(), SinkError> {
+        io::stdout().flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{FileSink, Record, Sink},
+        crate::{Config, Encoding, SinkConfig, SlotStatusEvent},
+        prost::Message,
+        std::{fs, path::Path, time::Duration},
+    };
+
+    fn send_slot(sink: &FileSink, slot: u64) {
+        let payload = SlotStatusEvent {
+            slot,
+            parent: slot - 1,
+            status: 0,
+        }
+        .encode_to_vec();
+        sink.send(Record {
+            topic: "slots",
+            key: &slot.to_le_bytes(),
+            payload: &payload,
+            slot: Some(slot),
+        })
+        .unwrap();
+    }
+
+    fn read_slots(path: &Path) -> Vec<u64> {
+        let data = fs::read(path).unwrap();
+        let mut buf = data.as_slice();
+        let mut slots = vec![];
+        while !buf.is_empty() {
+            slots.push(
+                SlotStatusEvent::decode_length_delimited(&mut buf)
+                    .unwrap()
+                    .slot,
+            );
+        }
+        slots
+    }
+
+    #[test]
+    fn test_file_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        let sink = FileSink::new(directory.path().to_owned(), 0, 10).unwrap();
+        for slot in 5..25 {
+            send_slot(&sink, slot);
+        }
+        // A late record goes to the current file.
+        send_slot(&sink, 15);
+        sink.flush(Duration::ZERO).unwrap();
+
+        let mut files = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["slots-10-1.pb", "slots-20-2.pb", "slots-5-0.pb"]
+        );
+
+        assert_eq!(
+            read_slots(&directory.path().join("slots-10-1.pb")),
+            (10..20).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            read_slots(&directory.path().join("slots-20-2.pb")),
+            vec![20, 21, 22, 23, 24, 15]
+        );
+
+        // After a restart, records go to new files rather than existing ones.
+        let sink = FileSink::new(directory.path().to_owned(), 0, 10).unwrap();
+        send_slot(&sink, 5);
+        sink.flush(Duration::ZERO).unwrap();
+        assert_eq!(
+            read_slots(&directory.path().join("slots-5-0.pb")),
+            (5..10).collect::<Vec<_>>()
+        );
+        assert_eq!(read_slots(&directory.path().join("slots-5-1.pb")), vec![5]);
+    }
+
+    #[test]
+    fn test_file_sink_size_rotation() {
+        let directory = tempfile::tempdir().unwrap();
+        let sink = FileSink::new(directory.path().to_owned(), 16, 0).unwrap();
+        for slot in 1..=4 {
+            send_slot(&sink, slot);
+        }
+        sink.flush(Duration::ZERO).unwrap();
+
+        // Records take 3 to 5 bytes, so the fourth one starts a new file.
+        let mut files = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, vec!["slots-1-0.pb", "slots-4-1.pb"]);
+    }
+
+    #[test]
+    fn test_file_sink_requires_protobuf() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = Config {
+            sink: SinkConfig::File {
+                directory: directory.path().to_owned(),
+                max_file_size: 0,
+                slots_per_file: 0,
+            },
+            encoding: Encoding::Json,
+            ..Config::default()
+        };
+        assert!(config.create_sink().is_err());
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashSet};
+
+/// Tree of the slots above the latest root, built from the parents in slot status updates.
+#[derive(Debug, Default)]
+pub struct SlotTree {
+    root: Option<u64>,
+    parents: BTreeMap<u64, Option<u64>>,
+    /// Slots above the root already returned as dead, so their late descendants are dead too.
+    dead: HashSet<u64>,
+}
+
+impl SlotTree {
+    /// Track a slot, keeping a previously known parent if none is given.
+    pub fn insert(&mut self, slot: u64, parent: Option<u64>) {
+        if self.root.map_or(false, |root| slot <= root) {
+            return;
+        }
+        let known = self.parents.entry(slot).or_default();
+        if parent.is_some() {
+            *known = parent;
+        }
+    }
+
+    /// Move the root to the slot and return the slots it made unreachable with their parents.
+    ///
+    /// Slots whose ancestry is unknown up to the root are kept until it is known.
+    pub fn set_root(&mut self, root: u64) -> Vec<(u64, Option<u64>)> {
+        if self.root.map_or(false, |previous| root <= previous) {
+            return Vec::new();
+        }
+
+        let mut ancestors = HashSet::new();
+        let mut next = Some(root);
+        while let Some(slot) = next {
+            ancestors.insert(slot);
+            next = self.parents.get(&slot).copied().flatten();
+        }
+        self.root = Some(root);
+
+        let dead = self
+            .parents
+            .iter()
+            .filter(|(slot, _)| !ancestors.contains(slot))
+            .filter(|(slot, _)| self.is_dead(**slot) == Some(true))
+            .map(|(slot, parent)| (*slot, *parent))
+            .collect::<Vec<_>>();
+        for (slot, _) in &dead {
+            self.parents.remove(slot);
+            self.dead.insert(*slot);
+        }
+        self.parents.retain(|slot, _| *slot > root);
+        self.dead.retain(|slot| *slot > root);
+        dead
+    }
+
+    /// Whether the slot descends from a slot below the root on another fork, if known.
+    fn is_dead(&self, mut slot: u64) -> Option<bool> {
+        let root = self.root?;
+        loop {
+            if slot == root {
+                return Some(false);
+            }
+            if slot < root || self.dead.contains(&slot) {
+                return Some(true);
+            }
+            slot = (*self.parents.get(&slot)?)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SlotTree;
+
+    #[test]
+    fn test_slot_tree() {
+        let mut tree = SlotTree::default();
+        // 10 - 11 - 13 - 15
+        //    \ 12 - 14
+        //         \ 16 - ?17
+        for (slot, parent) in [
+            (10, Some(9)),
+            (11, Some(10)),
+            (12, Some(10)),
+            (13, Some(11)),
+            (14, Some(12)),
+            (15, Some(13)),
+            (16, Some(12)),
+            (17, None),
+        ] {
+            tree.insert(slot, parent);
+        }
+        tree.insert(13, None);
+
+        assert_eq!(
+            tree.set_root(13),
+            vec![(12, Some(10)), (14, Some(12)), (16, Some(12))]
+        );
+        assert!(tree.set_root(11).is_empty());
+        tree.insert(12, Some(10));
+        tree.insert(18, Some(15));
+
+        tree.insert(17, Some(16));
+        assert_eq!(tree.set_root(15), vec![(17, Some(16))]);
+        assert_eq!(tree.parents.keys().copied().collect::<Vec<_>>(), vec![18]);
+    }
+}
@@ -0,0 +1,436 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        prom::{SPILL_BYTES, SPILL_DRAIN_LAG_SECONDS, SPILL_RECORDS_TOTAL},
+        Record,
+    },
+    log::warn,
+    prost::encoding::encode_varint,
+    std::{
+        collections::VecDeque,
+        fmt,
+        fs::{self, File, OpenOptions},
+        io::{self, BufReader, BufWriter, Read, Write},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Write-ahead buffer of records the producer could not take, stored in segment files.
+///
+/// Segments are named by sequence number and drained oldest first, including segments
+/// left over from a previous run. Segments are read back a record at a time.
+pub struct SpillBuffer {
+    directory: PathBuf,
+    max_segment_size: u64,
+    max_size: u64,
+    /// Whether any records are spilled, to skip the lock while the spill is empty.
+    spilled: AtomicBool,
+    state: Mutex<SpillState>,
+}
+
+impl fmt::Debug for SpillBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpillBuffer")
+            .field("directory", &self.directory)
+            .field("max_segment_size", &self.max_segment_size)
+            .field("max_size", &self.max_size)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Default)]
+struct SpillState {
+    /// Closed segments waiting to be drained, oldest first.
+    segments: VecDeque<u64>,
+    writer: Option<SegmentWriter>,
+    reader: Option<SegmentReader>,
+    next_sequence: u64,
+    /// Bytes in all segments, including drained parts of the one being read.
+    size: u64,
+}
+
+struct SegmentWriter {
+    sequence: u64,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+struct SegmentReader {
+    sequence: u64,
+    reader: BufReader<File>,
+    /// Bytes of the segment not drained yet.
+    remaining: u64,
+    /// The record read last, with its encoded length, until `send` takes it.
+    pending: Option<(SpilledRecord, usize)>,
+}
+
+/// A record read back from a segment.
+struct SpilledRecord {
+    topic: String,
+    key: Vec<u8>,
+    payload: Vec<u8>,
+    slot: Option<u64>,
+    timestamp_ms: u64,
+}
+
+impl SpillBuffer {
+    /// A zero `max_size` leaves the total size unbounded.
+    pub fn new(directory: PathBuf, max_segment_size: u64, max_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(&directory)?;
+        let mut state = SpillState::default();
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(sequence) = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".spill"))
+                .and_then(|sequence| sequence.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            segments.push(sequence);
+            state.size += entry.metadata()?.len();
+        }
+        segments.sort_unstable();
+        state.next_sequence = segments.last().map_or(0, |sequence| sequence + 1);
+        state.segments = segments.into();
+        SPILL_BYTES.set(state.size as i64);
+
+        Ok(Self {
+            directory,
+            max_segment_size,
+            max_size,
+            spilled: AtomicBool::new(state.size > 0),
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.spilled.load(Ordering::Acquire)
+    }
+
+    /// Append a record, returning `false` if the buffer is full.
+    pub fn append(&self, record: Record) -> io::Result<bool> {
+        let timestamp_ms = now_ms();
+        let mut buf = Vec::with_capacity(record.key.len() + record.payload.len() + 64);
+        encode_varint(timestamp_ms, &mut buf);
+        encode_varint(record.slot.map_or(0, |slot| slot + 1), &mut buf);
+        for field in [record.topic.as_bytes(), record.key, record.payload] {
+            encode_varint(field.len() as u64, &mut buf);
+            buf.extend_from_slice(field);
+        }
+
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        if self.max_size > 0 && state.size + buf.len() as u64 > self.max_size {
+            SPILL_RECORDS_TOTAL.with_label_values(&["dropped"]).inc();
+            return Ok(false);
+        }
+        let full = matches!(
+            &state.writer,
+            Some(writer) if writer.size > 0 && writer.size + buf.len() as u64 > self.max_segment_size
+        );
+        if full {
+            self.close_writer(state)?;
+        }
+        if state.writer.is_none() {
+            let sequence = state.next_sequence;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(sequence))?;
+            state.next_sequence += 1;
+            state.writer = Some(SegmentWriter {
+                sequence,
+                writer: BufWriter::new(file),
+                size: 0,
+            });
+        }
+        let writer = state.writer.as_mut().expect("writer is open");
+        writer.writer.write_all(&buf)?;
+        writer.size += buf.len() as u64;
+        state.size += buf.len() as u64;
+        self.spilled.store(true, Ordering::Release);
+        SPILL_BYTES.set(state.size as i64);
+        SPILL_RECORDS_TOTAL.with_label_values(&["spilled"]).inc();
+        Ok(true)
+    }
+
+    /// Hand records to `send` in order until it refuses one or the buffer is empty.
+    pub fn drain<F: FnMut(Record) -> bool>(&self, mut send: F) -> io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        loop {
+            if state.reader.is_none() {
+                if state.segments.is_empty() {
+                    if state.writer.is_none() {
+                        break;
+                    }
+                    self.close_writer(state)?;
+                }
+                let sequence = state.segments.pop_front().expect("segment is queued");
+                state.reader = Some(SegmentReader::open(&self.path(sequence), sequence)?);
+            }
+
+            let reader = state.reader.as_mut().expect("reader is open");
+            if reader.pending.is_none() {
+                reader.pending = reader.next();
+            }
+            let Some((record, len)) = &reader.pending else {
+                let sequence = reader.sequence;
+                let remaining = reader.remaining;
+                state.reader = None;
+                state.size = state.size.saturating_sub(remaining);
+                fs::remove_file(self.path(sequence))?;
+                continue;
+            };
+            let sent = send(Record {
+                topic: &record.topic,
+                key: &record.key,
+                payload: &record.payload,
+                slot: record.slot,
+            });
+            let lag_ms = now_ms().saturating_sub(record.timestamp_ms);
+            SPILL_DRAIN_LAG_SECONDS.set(lag_ms as f64 / 1000.0);
+            if !sent {
+                break;
+            }
+            let len = *len as u64;
+            reader.pending = None;
+            reader.remaining -= len;
+            state.size -= len;
+            SPILL_RECORDS_TOTAL.with_label_values(&["drained"]).inc();
+        }
+        if state.size == 0 {
+            self.spilled.store(false, Ordering::Release);
+            SPILL_DRAIN_LAG_SECONDS.set(0.0);
+        }
+        SPILL_BYTES.set(state.size as i64);
+        Ok(())
+    }
+
+    /// Write out buffered records of the open segment.
+    pub fn flush(&self) -> io::Result<()> {
+        match &mut self.state.lock().unwrap().writer {
+            Some(writer) => writer.writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close_writer(&self, state: &mut SpillState) -> io::Result<()> {
+        if let Some(mut writer) = state.writer.take() {
+            writer.writer.flush()?;
+            state.segments.push_back(writer.sequence);
+        }
+        Ok(())
+    }
+
+    fn path(&self, sequence: u64) -> PathBuf {
+        self.directory.join(format!("{sequence:020}.spill"))
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl SegmentReader {
+    fn open(path: &Path, sequence: u64) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(Self {
+            sequence,
+            remaining: file.metadata()?.len(),
+            reader: BufReader::new(file),
+            pending: None,
+        })
+    }
+
+    /// Read the next record and return it with its encoded length.
+    ///
+    /// A record cut short, e.g. by a crash while appending, ends the segment.
+    fn next(&mut self) -> Option<(SpilledRecord, usize)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let record = self.decode();
+        if record.is_none() {
+            warn!(
+                "Discarding {} bytes of corrupt spill segment {}",
+                self.remaining, self.sequence
+            );
+        }
+        record
+    }
+
+    fn decode(&mut self) -> Option<(SpilledRecord, usize)> {
+        let mut len = 0;
+        let timestamp_ms = self.read_varint(&mut len)?;
+        let slot = self.read_varint(&mut len)?.checked_sub(1);
+        let mut fields = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let field_len = self.read_varint(&mut len)?;
+            if field_len > self.remaining - len as u64 {
+                return None;
+            }
+            let mut field = vec![0; field_len as usize];
+            self.reader.read_exact(&mut field).ok()?;
+            len += field.len();
+            fields.push(field);
+        }
+        let payload = fields.pop()?;
+        let key = fields.pop()?;
+        let topic = String::from_utf8(fields.pop()?).ok()?;
+        let record = SpilledRecord {
+            topic,
+            key,
+            payload,
+            slot,
+            timestamp_ms,
+        };
+        Some((record, len))
+    }
+
+    /// Read a varint, adding its encoded length to `len`.
+    fn read_varint(&mut self, len: &mut usize) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            if *len as u64 >= self.remaining {
+                return None;
+            }
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte).ok()?;
+            *len += 1;
+            value |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::SpillBuffer,
+        crate::Record,
+        std::fs::{self, OpenOptions},
+    };
+
+    fn append(spill: &SpillBuffer, key: u8) -> bool {
+        spill
+            .append(Record {
+                topic: "accounts",
+                key: &[key],
+                payload: &[key; 8],
+                slot: Some(key as u64),
+            })
+            .unwrap()
+    }
+
+    fn drain(spill: &SpillBuffer, limit: usize) -> Vec<u8> {
+        let mut keys = vec![];
+        spill
+            .drain(|record| {
+                if keys.len() == limit {
+                    return false;
+                }
+                assert_eq!(record.topic, "accounts");
+                assert_eq!(record.slot, Some(record.key[0] as u64));
+                keys.push(record.key[0]);
+                true
+            })
+            .unwrap();
+        keys
+    }
+
+    #[test]
+    fn test_spill_buffer() {
+        let directory = tempfile::tempdir().unwrap();
+        let spill = SpillBuffer::new(directory.path().to_owned(), 64, 0).unwrap();
+        assert!(spill.is_empty());
+        for key in 0..10 {
+            assert!(append(&spill, key));
+        }
+        assert!(fs::read_dir(directory.path()).unwrap().count() > 1);
+
+        assert_eq!(drain(&spill, 3), vec![0, 1, 2]);
+        assert!(!spill.is_empty());
+        assert!(append(&spill, 10));
+        assert_eq!(drain(&spill, 100), (3..=10).collect::<Vec<_>>());
+        assert!(spill.is_empty());
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_spill_buffer_reopen() {
+        let directory = tempfile::tempdir().unwrap();
+        {
+            // Records take 27 bytes, so five of them fit.
+            let spill = SpillBuffer::new(directory.path().to_owned(), 64, 150).unwrap();
+            let mut appended = 0;
+            while append(&spill, appended) {
+                appended += 1;
+            }
+            assert_eq!(appended, 5);
+            spill.flush().unwrap();
+        }
+
+        let spill = SpillBuffer::new(directory.path().to_owned(), 64, 150).unwrap();
+        assert!(!spill.is_empty());
+        assert_eq!(drain(&spill, 100), vec![0, 1, 2, 3, 4]);
+        assert!(spill.is_empty());
+    }
+
+    #[test]
+    fn test_spill_buffer_truncated() {
+        let directory = tempfile::tempdir().unwrap();
+        {
+            let spill = SpillBuffer::new(directory.path().to_owned(), 1024, 0).unwrap();
+            for key in 0..3 {
+                assert!(append(&spill, key));
+            }
+            spill.flush().unwrap();
+        }
+
+        // A crash while appending leaves the last record cut short.
+        let path = fs::read_dir(directory.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+
+        let spill = SpillBuffer::new(directory.path().to_owned(), 1024, 0).unwrap();
+        assert_eq!(drain(&spill, 100), vec![0, 1]);
+        assert!(spill.is_empty());
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+}
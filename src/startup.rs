@@ -0,0 +1,104 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{startup_complete_event::ProgramAccountCount, StartupCompleteEvent},
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            RwLock,
+        },
+    },
+};
+
+/// Per-program account counts collected while the validator replays its snapshot.
+///
+/// Counts are atomic, so that the snapshot threads only share a read lock once every owner
+/// program has been seen.
+#[derive(Debug, Default)]
+pub struct StartupStats {
+    programs: RwLock<HashMap<Vec<u8>, Counts>>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    published: AtomicU64,
+    filtered: AtomicU64,
+}
+
+impl StartupStats {
+    pub fn record_published(&self, owner: &[u8]) {
+        self.with_counts(owner, |counts| &counts.published);
+    }
+
+    pub fn record_filtered(&self, owner: &[u8]) {
+        self.with_counts(owner, |counts| &counts.filtered);
+    }
+
+    /// Drain the collected counts into a [`StartupCompleteEvent`].
+    pub fn take_event(&self) -> StartupCompleteEvent {
+        let programs = std::mem::take(&mut *self.programs.write().unwrap());
+        let mut programs = programs
+            .into_iter()
+            .map(|(program, counts)| ProgramAccountCount {
+                program,
+                published: counts.published.into_inner(),
+                filtered: counts.filtered.into_inner(),
+            })
+            .collect::<Vec<_>>();
+        programs.sort_by(|a, b| a.program.cmp(&b.program));
+
+        StartupCompleteEvent {
+            accounts_published: programs.iter().map(|p| p.published).sum(),
+            accounts_filtered: programs.iter().map(|p| p.filtered).sum(),
+            programs,
+        }
+    }
+
+    fn with_counts(&self, owner: &[u8], counter: impl Fn(&Counts) -> &AtomicU64) {
+        if let Some(counts) = self.programs.read().unwrap().get(owner) {
+            counter(counts).fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let mut programs = self.programs.write().unwrap();
+        let counts = programs.entry(owner.to_vec()).or_default();
+        counter(counts).fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StartupStats;
+
+    #[test]
+    fn test_startup_stats() {
+        let stats = StartupStats::default();
+        stats.record_published(&[2; 32]);
+        stats.record_published(&[1; 32]);
+        stats.record_published(&[1; 32]);
+        stats.record_filtered(&[2; 32]);
+
+        let event = stats.take_event();
+        assert_eq!(event.accounts_published, 3);
+        assert_eq!(event.accounts_filtered, 1);
+        assert_eq!(event.programs.len(), 2);
+        assert_eq!(event.programs[0].program, vec![1; 32]);
+        assert_eq!(event.programs[0].published, 2);
+        assert_eq!(event.programs[1].filtered, 1);
+
+        let event = stats.take_event();
+        assert!(event.programs.is_empty());
+    }
+}
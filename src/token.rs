@@ -0,0 +1,236 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing of SPL Token and Token-2022 account data, following the layouts of
+//! `spl_token_2022::state`.
+
+use {
+    crate::{update_account_event::TokenState, MintState, TokenAccountState, TokenExtension},
+    solana_program::{pubkey, pubkey::Pubkey},
+};
+
+pub const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGhPSWxeDZx4S6nKH7k8VyF");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+const ACCOUNT_LEN: usize = 165;
+const MINT_LEN: usize = 82;
+const MULTISIG_LEN: usize = 355;
+
+/// `spl_token_2022::extension::AccountType` values, stored right after the account layout.
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+/// Parse the data of an initialized token account or mint owned by one of the token programs.
+pub fn parse_token_state(owner: &[u8], data: &[u8]) -> Option<TokenState> {
+    if owner == TOKEN_PROGRAM_ID.as_ref() {
+        match data.len() {
+            ACCOUNT_LEN => parse_account(data, Vec::new()),
+            MINT_LEN => parse_mint(data, Vec::new()),
+            _ => None,
+        }
+    } else if owner == TOKEN_2022_PROGRAM_ID.as_ref() {
+        match data.len() {
+            ACCOUNT_LEN => parse_account(data, Vec::new()),
+            MINT_LEN => parse_mint(data, Vec::new()),
+            MULTISIG_LEN => None,
+            len if len > ACCOUNT_LEN => {
+                let extensions = parse_extensions(&data[ACCOUNT_LEN + 1..]);
+                match data[ACCOUNT_LEN] {
+                    ACCOUNT_TYPE_ACCOUNT => parse_account(&data[..ACCOUNT_LEN], extensions),
+                    // Mints are padded to the account length.
+                    ACCOUNT_TYPE_MINT => parse_mint(&data[..MINT_LEN], extensions),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+fn parse_account(data: &[u8], extensions: Vec<TokenExtension>) -> Option<TokenState> {
+    let state = data[108];
+    if state == 0 || state > 2 {
+        return None;
+    }
+    Some(TokenState::TokenAccount(TokenAccountState {
+        mint: data[0..32].to_vec(),
+        owner: data[32..64].to_vec(),
+        amount: read_u64(&data[64..72]),
+        delegate: read_pubkey_option(&data[72..108])?,
+        state: state as i32,
+        is_native: read_u64_option(&data[109..121])?,
+        delegated_amount: read_u64(&data[121..129]),
+        close_authority: read_pubkey_option(&data[129..165])?,
+        extensions,
+    }))
+}
+
+fn parse_mint(data: &[u8], extensions: Vec<TokenExtension>) -> Option<TokenState> {
+    if data[45] != 1 {
+        return None;
+    }
+    Some(TokenState::Mint(MintState {
+        mint_authority: read_pubkey_option(&data[0..36])?,
+        supply: read_u64(&data[36..44]),
+        decimals: data[44] as u32,
+        is_initialized: true,
+        freeze_authority: read_pubkey_option(&data[46..82])?,
+        extensions,
+    }))
+}
+
+/// Read the type-length-value entries following the account type.
+fn parse_extensions(mut data: &[u8]) -> Vec<TokenExtension> {
+    let mut extensions = Vec::new();
+    while data.len() >= 4 {
+        let extension_type = u16::from_le_bytes([data[0], data[1]]);
+        let len = u16::from_le_bytes([data[2], data[3]]) as usize;
+        // Space past the last extension is zeroed, reading as the uninitialized type.
+        if extension_type == 0 || data.len() < 4 + len {
+            break;
+        }
+        extensions.push(TokenExtension {
+            extension_type: extension_type as u32,
+            data: data[4..4 + len].to_vec(),
+        });
+        data = &data[4 + len..];
+    }
+    extensions
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    u64::from_le_bytes(data.try_into().unwrap())
+}
+
+/// Read a `COption`, failing on an invalid tag.
+fn read_option(data: &[u8]) -> Option<Option<&[u8]>> {
+    match data[..4] {
+        [0, 0, 0, 0] => Some(None),
+        [1, 0, 0, 0] => Some(Some(&data[4..])),
+        _ => None,
+    }
+}
+
+fn read_pubkey_option(data: &[u8]) -> Option<Option<Vec<u8>>> {
+    read_option(data).map(|value| value.map(|pubkey| pubkey.to_vec()))
+}
+
+fn read_u64_option(data: &[u8]) -> Option<Option<u64>> {
+    read_option(data).map(|value| value.map(read_u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{parse_token_state, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID},
+        crate::{
+            update_account_event::TokenState, MintState, TokenAccountState, TokenAccountStatus,
+            TokenExtension,
+        },
+    };
+
+    fn account_data() -> Vec<u8> {
+        let mut data = vec![0; 165];
+        data[0..32].fill(1);
+        data[32..64].fill(2);
+        data[64..72].copy_from_slice(&500u64.to_le_bytes());
+        data[72] = 1;
+        data[76..108].fill(3);
+        data[108] = 2;
+        data[121..129].copy_from_slice(&100u64.to_le_bytes());
+        data
+    }
+
+    fn account_state(extensions: Vec<TokenExtension>) -> TokenState {
+        TokenState::TokenAccount(TokenAccountState {
+            mint: vec![1; 32],
+            owner: vec![2; 32],
+            amount: 500,
+            delegate: Some(vec![3; 32]),
+            state: TokenAccountStatus::Frozen.into(),
+            is_native: None,
+            delegated_amount: 100,
+            close_authority: None,
+            extensions,
+        })
+    }
+
+    #[test]
+    fn test_parse_token_account() {
+        let data = account_data();
+        let token = TOKEN_PROGRAM_ID.to_bytes();
+        assert_eq!(
+            parse_token_state(&token, &data),
+            Some(account_state(vec![]))
+        );
+        assert_eq!(parse_token_state(&[0; 32], &data), None);
+
+        let mut uninitialized = data.clone();
+        uninitialized[108] = 0;
+        assert_eq!(parse_token_state(&token, &uninitialized), None);
+
+        // A Token-2022 account with the immutable owner extension and zeroed spare space.
+        let mut data = data;
+        data.extend_from_slice(&[2, 7, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            parse_token_state(&TOKEN_2022_PROGRAM_ID.to_bytes(), &data),
+            Some(account_state(vec![TokenExtension {
+                extension_type: 7,
+                data: vec![],
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_parse_mint() {
+        let mut data = vec![0; 82];
+        data[36..44].copy_from_slice(&1_000_000u64.to_le_bytes());
+        data[44] = 6;
+        data[45] = 1;
+        data[46] = 1;
+        data[50..82].fill(4);
+        let mint = TokenState::Mint(MintState {
+            mint_authority: None,
+            supply: 1_000_000,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: Some(vec![4; 32]),
+            extensions: vec![],
+        });
+        assert_eq!(
+            parse_token_state(&TOKEN_PROGRAM_ID.to_bytes(), &data),
+            Some(mint)
+        );
+
+        // A Token-2022 mint with a mint close authority, padded to the account length.
+        data.resize(165, 0);
+        data.push(1);
+        data.extend_from_slice(&[3, 0, 32, 0]);
+        data.extend_from_slice(&[5; 32]);
+        let Some(TokenState::Mint(mint)) =
+            parse_token_state(&TOKEN_2022_PROGRAM_ID.to_bytes(), &data)
+        else {
+            panic!("mint not parsed");
+        };
+        assert_eq!(
+            mint.extensions,
+            vec![TokenExtension {
+                extension_type: 3,
+                data: vec![5; 32],
+            }]
+        );
+    }
+}
@@ -0,0 +1,428 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        prom::{StatsThreadedProducerContext, KAFKA_TRANSACTIONS_TOTAL},
+        Commitment, Record, Sink, SinkError, SlotBuffer, SlotStatus, TransactionsConfig,
+    },
+    log::{error, warn},
+    rdkafka::{
+        error::{KafkaError, KafkaResult, RDKafkaErrorCode},
+        producer::{BaseRecord, Producer, ThreadedProducer},
+    },
+    std::{
+        sync::Mutex,
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+/// The transaction operations of a Kafka producer used by [`TransactionalSink`].
+pub trait TransactionalProducer: Send + Sync + 'static {
+    fn init_transactions(&self, timeout: Duration) -> KafkaResult<()>;
+    fn begin_transaction(&self) -> KafkaResult<()>;
+    /// Queue a record in the open transaction without waiting for room in the queue.
+    fn send_record(&self, topic: &str, key: &[u8], payload: &[u8]) -> KafkaResult<()>;
+    fn commit_transaction(&self, timeout: Duration) -> KafkaResult<()>;
+    fn abort_transaction(&self, timeout: Duration) -> KafkaResult<()>;
+    fn flush(&self, timeout: Duration) -> KafkaResult<()>;
+}
+
+impl TransactionalProducer for ThreadedProducer<StatsThreadedProducerContext> {
+    fn init_transactions(&self, timeout: Duration) -> KafkaResult<()> {
+        Producer::init_transactions(self, timeout)
+    }
+
+    fn begin_transaction(&self) -> KafkaResult<()> {
+        Producer::begin_transaction(self)
+    }
+
+    fn send_record(&self, topic: &str, key: &[u8], payload: &[u8]) -> KafkaResult<()> {
+        let record = BaseRecord::<[u8], [u8], usize>::with_opaque_to(topic, 0)
+            .key(key)
+            .payload(payload);
+        self.send(record).map_err(|(error, _)| error)
+    }
+
+    fn commit_transaction(&self, timeout: Duration) -> KafkaResult<()> {
+        Producer::commit_transaction(self, timeout)
+    }
+
+    fn abort_transaction(&self, timeout: Duration) -> KafkaResult<()> {
+        Producer::abort_transaction(self, timeout)
+    }
+
+    fn flush(&self, timeout: Duration) -> KafkaResult<()> {
+        Producer::flush(self, timeout)
+    }
+}
+
+/// Publishes the records of each slot in a Kafka transaction once the slot reaches the
+/// configured commitment.
+///
+/// Records are buffered per slot until then. Buffered slots that turn out to be on abandoned
+/// forks are discarded, or committed as well if `abort_on_fork` is disabled. When the memory
+/// limits are exceeded, the oldest slots are committed before reaching the commitment, so a
+/// slot larger than `max_bytes`, like the startup accounts of the snapshot slot, is committed
+/// in chunks. Records without a slot are committed right away, one transaction each.
+pub struct TransactionalSink<P = ThreadedProducer<StatsThreadedProducerContext>> {
+    producer: P,
+    commit_on: Commitment,
+    abort_on_fork: bool,
+    timeout: Duration,
+    max_slots: usize,
+    max_bytes: usize,
+    slots: Mutex<SlotBuffer<OwnedRecord>>,
+    /// Held while committing, as transactions of a producer can't overlap. Taken before the
+    /// slots lock when both are needed.
+    commit_lock: Mutex<()>,
+}
+
+#[derive(Debug)]
+struct OwnedRecord {
+    topic: String,
+    key: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl<P: TransactionalProducer> TransactionalSink<P> {
+    /// The producer must be configured with a `transactional.id`.
+    pub fn new(producer: P, config: &TransactionsConfig) -> Result<Self, KafkaError> {
+        let timeout = Duration::from_millis(config.timeout_ms);
+        producer.init_transactions(timeout)?;
+        Ok(Self {
+            producer,
+            commit_on: config.commit_on,
+            abort_on_fork: config.abort_on_fork,
+            timeout,
+            max_slots: config.max_slots,
+            max_bytes: config.max_bytes,
+            slots: Mutex::new(SlotBuffer::default()),
+            commit_lock: Mutex::new(()),
+        })
+    }
+
+    fn over_limits(&self, slots: &SlotBuffer<OwnedRecord>) -> bool {
+        slots.len() > self.max_slots.max(1) || slots.size() > self.max_bytes
+    }
+
+    /// Commit each slot in its own transaction, carrying on with the next slot after a failure.
+    ///
+    /// Returns the first error. The caller must hold the commit lock.
+    fn commit_slots(&self, slots: Vec<(u64, Vec<OwnedRecord>)>) -> Result<(), SinkError> {
+        let mut result = Ok(());
+        for (slot, records) in slots {
+            if let Err(error) = self.commit(&records) {
+                error!(
+                    "Failed to commit {} records of slot {slot}: {error}",
+                    records.len()
+                );
+                if result.is_ok() {
+                    result = Err(error);
+                }
+            }
+        }
+        result
+    }
+
+    /// Publish the records in a single transaction.
+    fn commit(&self, records: &[OwnedRecord]) -> Result<(), SinkError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.producer.begin_transaction()?;
+        let result = records
+            .iter()
+            .try_for_each(|record| self.produce(record))
+            .and_then(|()| self.producer.commit_transaction(self.timeout));
+        if let Err(error) = result {
+            KAFKA_TRANSACTIONS_TOTAL
+                .with_label_values(&["failed"])
+                .inc();
+            if let Err(abort_error) = self.producer.abort_transaction(self.timeout) {
+                error!("Failed to abort kafka transaction: {abort_error}");
+            }
+            return Err(error.into());
+        }
+        KAFKA_TRANSACTIONS_TOTAL
+            .with_label_values(&["committed"])
+            .inc();
+        Ok(())
+    }
+
+    /// Queue a record, waiting for room in the producer queue up to the timeout.
+    fn produce(&self, record: &OwnedRecord) -> Result<(), KafkaError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self
+                .producer
+                .send_record(&record.topic, &record.key, &record.payload)
+            {
+                Err(KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull))
+                    if Instant::now() < deadline =>
+                {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<P: TransactionalProducer> Sink for TransactionalSink<P> {
+    fn send(&self, record: Record) -> Result<(), SinkError> {
+        let owned = OwnedRecord {
+            topic: record.topic.to_owned(),
+            key: record.key.to_vec(),
+            payload: record.payload.to_vec(),
+        };
+        let Some(slot) = record.slot else {
+            let _commit = self.commit_lock.lock().unwrap();
+            return self.commit(&[owned]);
+        };
+
+        {
+            let mut slots = self.slots.lock().unwrap();
+            let size = owned.key.len() + owned.payload.len();
+            slots.push(slot, owned, size);
+            if !self.over_limits(&slots) {
+                return Ok(());
+            }
+        }
+
+        // Popping under the commit lock keeps the slots committed in order.
+        let _commit = self.commit_lock.lock().unwrap();
+        let mut released = Vec::new();
+        {
+            let mut slots = self.slots.lock().unwrap();
+            while self.over_limits(&slots) {
+                let Some(oldest) = slots.pop_oldest() else {
+                    break;
+                };
+                released.push(oldest);
+            }
+        }
+        if released.is_empty() {
+            return Ok(());
+        }
+        warn!(
+            "Committing {} slots before reaching the commitment to stay within limits",
+            released.len()
+        );
+        KAFKA_TRANSACTIONS_TOTAL
+            .with_label_values(&["released"])
+            .inc_by(released.len() as u64);
+        self.commit_slots(released)
+    }
+
+    fn update_slot(
+        &self,
+        slot: u64,
+        parent: Option<u64>,
+        status: SlotStatus,
+    ) -> Result<(), SinkError> {
+        let reached = self.commit_on.reached_by(status);
+        // Settling under the commit lock keeps the slots committed in order.
+        let _commit = reached.then(|| self.commit_lock.lock().unwrap());
+        let settled = {
+            let mut slots = self.slots.lock().unwrap();
+            if let Some(parent) = parent {
+                slots.set_parent(slot, parent);
+            }
+            if !reached {
+                return Ok(());
+            }
+            slots.settle(slot)
+        };
+
+        let mut committed = settled.committed;
+        for (fork, records) in settled.abandoned {
+            if self.abort_on_fork {
+                warn!(
+                    "Discarding {} records of slot {fork} on abandoned fork",
+                    records.len()
+                );
+                KAFKA_TRANSACTIONS_TOTAL
+                    .with_label_values(&["aborted"])
+                    .inc();
+            } else {
+                committed.push((fork, records));
+            }
+        }
+        committed.sort_by_key(|(slot, _)| *slot);
+        self.commit_slots(committed)
+    }
+
+    fn flush(&self, timeout: Duration) -> Result<(), SinkError> {
+        self.producer.flush(timeout).map_err(SinkError::Kafka)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{TransactionalProducer, TransactionalSink},
+        crate::{
+            slot_tree::SlotTree, Commitment, Config, Publisher, Record, Sink, SlotStatus,
+            SlotStatusEvent, TransactionsConfig,
+        },
+        prost::Message,
+        rdkafka::error::KafkaResult,
+        std::{
+            sync::{Arc, Mutex},
+            time::Duration,
+        },
+    };
+
+    type Committed = Arc<Mutex<Vec<Vec<Vec<u8>>>>>;
+
+    /// Stub of a producer that keeps the payloads of each committed transaction.
+    #[derive(Default)]
+    struct MemoryProducer {
+        open: Mutex<Vec<Vec<u8>>>,
+        committed: Committed,
+    }
+
+    impl TransactionalProducer for MemoryProducer {
+        fn init_transactions(&self, _timeout: Duration) -> KafkaResult<()> {
+            Ok(())
+        }
+
+        fn begin_transaction(&self) -> KafkaResult<()> {
+            self.open.lock().unwrap().clear();
+            Ok(())
+        }
+
+        fn send_record(&self, _topic: &str, _key: &[u8], payload: &[u8]) -> KafkaResult<()> {
+            self.open.lock().unwrap().push(payload.to_vec());
+            Ok(())
+        }
+
+        fn commit_transaction(&self, _timeout: Duration) -> KafkaResult<()> {
+            let records = std::mem::take(&mut *self.open.lock().unwrap());
+            self.committed.lock().unwrap().push(records);
+            Ok(())
+        }
+
+        fn abort_transaction(&self, _timeout: Duration) -> KafkaResult<()> {
+            self.open.lock().unwrap().clear();
+            Ok(())
+        }
+
+        fn flush(&self, _timeout: Duration) -> KafkaResult<()> {
+            Ok(())
+        }
+    }
+
+    fn sink(
+        commit_on: Commitment,
+        max_bytes: usize,
+    ) -> (TransactionalSink<MemoryProducer>, Committed) {
+        let producer = MemoryProducer::default();
+        let committed = producer.committed.clone();
+        let config = TransactionsConfig {
+            transactional_id: "test".to_owned(),
+            commit_on,
+            abort_on_fork: true,
+            timeout_ms: 1_000,
+            max_slots: 256,
+            max_bytes,
+        };
+        let sink = TransactionalSink::new(producer, &config).unwrap();
+        (sink, committed)
+    }
+
+    #[test]
+    fn test_dead_slot_committed() {
+        let (sink, committed) = sink(Commitment::Rooted, 1024 * 1024);
+        let config = Config {
+            slot_status_topic: "slots".to_owned(),
+            ..Config::default()
+        };
+        let publisher = Publisher::new(Box::new(sink), None, &config);
+        let mut tree = SlotTree::default();
+        let update = |slot, parent, status: SlotStatus| {
+            publisher
+                .update_slot_status(SlotStatusEvent {
+                    slot,
+                    parent,
+                    status: status.into(),
+                })
+                .unwrap();
+        };
+
+        // 10 - 11
+        //    \ 12
+        for (slot, parent) in [(11, 10), (12, 10)] {
+            tree.insert(slot, Some(parent));
+            update(slot, parent, SlotStatus::Processed);
+        }
+        let dead = tree.set_root(12);
+        assert_eq!(dead, vec![(11, Some(10))]);
+        update(12, 10, SlotStatus::Rooted);
+        for (slot, parent) in dead {
+            update(slot, parent.unwrap_or(0), SlotStatus::Dead);
+        }
+
+        let statuses = committed
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|records| {
+                records
+                    .iter()
+                    .map(|payload| {
+                        let ev = SlotStatusEvent::decode(payload.as_slice()).unwrap();
+                        (ev.slot, SlotStatus::from_i32(ev.status).unwrap())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                vec![(12, SlotStatus::Processed), (12, SlotStatus::Rooted)],
+                vec![(11, SlotStatus::Dead)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_slot_committed_in_chunks() {
+        let (sink, committed) = sink(Commitment::Confirmed, 12);
+        for payload in [b"abcd", b"efgh", b"ijkl", b"mnop"] {
+            sink.send(Record {
+                topic: "accounts",
+                key: b"k",
+                payload,
+                slot: Some(5),
+            })
+            .unwrap();
+        }
+        assert_eq!(
+            *committed.lock().unwrap(),
+            vec![vec![b"abcd".to_vec(), b"efgh".to_vec(), b"ijkl".to_vec()]]
+        );
+
+        sink.update_slot(5, None, SlotStatus::Confirmed).unwrap();
+        assert_eq!(
+            *committed.lock().unwrap(),
+            vec![
+                vec![b"abcd".to_vec(), b"efgh".to_vec(), b"ijkl".to_vec()],
+                vec![b"mnop".to_vec()],
+            ]
+        );
+    }
+}